@@ -36,6 +36,8 @@ pub enum RejectedReason {
     Replaced,
     /// Attempt to delete a non-owned event
     InvalidDelete,
+    /// The event ID or signature is invalid
+    InvalidSignature,
     /// Other reason
     Other,
 }
@@ -49,6 +51,7 @@ impl From<prelude::RejectedReason> for RejectedReason {
             prelude::RejectedReason::Expired => Self::Expired,
             prelude::RejectedReason::Replaced => Self::Replaced,
             prelude::RejectedReason::InvalidDelete => Self::InvalidDelete,
+            prelude::RejectedReason::InvalidSignature => Self::InvalidSignature,
             prelude::RejectedReason::Other => Self::Other,
         }
     }
@@ -147,8 +150,10 @@ impl NostrDatabase {
     }
 
     /// Delete all events that match the `Filter`
-    pub async fn delete(&self, filter: &Filter) -> Result<()> {
-        Ok(self.inner.delete(filter.deref().clone()).await?)
+    ///
+    /// Return the number of deleted events.
+    pub async fn delete(&self, filter: &Filter) -> Result<u64> {
+        Ok(self.inner.delete(filter.deref().clone()).await? as u64)
     }
 
     /// Wipe all data
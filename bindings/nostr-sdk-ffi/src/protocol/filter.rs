@@ -441,6 +441,12 @@ pub struct GenericTag {
     pub value: Vec<String>,
 }
 
+#[derive(Record)]
+pub struct MultiLetterTag {
+    pub key: String,
+    pub value: Vec<String>,
+}
+
 #[derive(Record)]
 pub struct FilterRecord {
     pub ids: Option<Vec<Arc<EventId>>>,
@@ -458,6 +464,8 @@ pub struct FilterRecord {
     pub limit: Option<u64>,
     /// Generic tag queries
     pub generic_tags: Vec<GenericTag>,
+    /// Multi-letter tag queries
+    pub multi_letter_tags: Vec<MultiLetterTag>,
 }
 
 impl From<nostr::Filter> for FilterRecord {
@@ -484,6 +492,14 @@ impl From<nostr::Filter> for FilterRecord {
                     value: v.into_iter().map(|v| v.to_string()).collect(),
                 })
                 .collect(),
+            multi_letter_tags: f
+                .multi_letter_tags
+                .into_iter()
+                .map(|(k, v)| MultiLetterTag {
+                    key: k,
+                    value: v.into_iter().collect(),
+                })
+                .collect(),
         }
     }
 }
@@ -507,6 +523,11 @@ impl From<FilterRecord> for nostr::Filter {
                 .into_iter()
                 .map(|GenericTag { key, value }| (**key, value.into_iter().collect()))
                 .collect(),
+            multi_letter_tags: f
+                .multi_letter_tags
+                .into_iter()
+                .map(|MultiLetterTag { key, value }| (key, value.into_iter().collect()))
+                .collect(),
         }
     }
 }
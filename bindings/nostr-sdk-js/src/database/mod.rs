@@ -33,6 +33,8 @@ pub enum JsSaveEventStatus {
     Replaced,
     /// Attempt to delete a non-owned event
     InvalidDelete,
+    /// The event ID or signature is invalid
+    InvalidSignature,
     /// Other reason
     Other,
 }
@@ -47,6 +49,7 @@ impl From<SaveEventStatus> for JsSaveEventStatus {
             SaveEventStatus::Rejected(RejectedReason::Expired) => Self::Expired,
             SaveEventStatus::Rejected(RejectedReason::Replaced) => Self::Replaced,
             SaveEventStatus::Rejected(RejectedReason::InvalidDelete) => Self::InvalidDelete,
+            SaveEventStatus::Rejected(RejectedReason::InvalidSignature) => Self::InvalidSignature,
             SaveEventStatus::Rejected(RejectedReason::Other) => Self::Other,
         }
     }
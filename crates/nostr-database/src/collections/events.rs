@@ -89,6 +89,9 @@ impl Events {
     /// Collection is converted to unbounded if one of the merge [`Events`] have a different hash.
     /// In other words, the filters limit is respected only if the [`Events`] are related to the same
     /// list of filters.
+    ///
+    /// Events are deduplicated by ID, keeping the instance of `self`, and sorted newest first.
+    /// In case of identical timestamps, the event with the lowest ID comes first.
     pub fn merge(mut self, other: Self) -> Self {
         // Hash not match -> change capacity to unbounded
         if self.hash != other.hash || self.prev_not_match || other.prev_not_match {
@@ -138,6 +141,27 @@ impl Events {
         self.set.iter()
     }
 
+    /// Retain only the events specified by the predicate
+    ///
+    /// The descending order is preserved.
+    #[inline]
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&Event) -> bool,
+    {
+        self.set.retain(f)
+    }
+
+    /// Filter and map the events into a vector, preserving the descending order
+    #[inline]
+    pub fn filter_map_into_vec<F, T>(self, f: F) -> Vec<T>
+    where
+        F: FnMut(Event) -> Option<T>,
+    {
+        // Lookup ID: EVENT_ORD_IMPL
+        self.into_iter().filter_map(f).collect()
+    }
+
     /// Convert the collection to vector of events.
     #[inline]
     pub fn to_vec(self) -> Vec<Event> {
@@ -145,6 +169,29 @@ impl Events {
     }
 }
 
+impl FromIterator<Event> for Events {
+    /// Collect the events in an unbounded collection
+    ///
+    /// Events are deduplicated by ID, keeping the first seen instance, and sorted newest first.
+    /// In case of identical timestamps, the event with the lowest ID comes first.
+    ///
+    /// The collection isn't related to any filter,
+    /// so merging it with other [`Events`] will always produce an unbounded collection.
+    fn from_iter<T>(iter: T) -> Self
+    where
+        T: IntoIterator<Item = Event>,
+    {
+        let mut set: BTreeCappedSet<Event> = BTreeCappedSet::unbounded();
+        set.extend(iter);
+
+        Self {
+            set,
+            hash: 0,
+            prev_not_match: true,
+        }
+    }
+}
+
 impl IntoIterator for Events {
     type Item = Event;
     type IntoIter = IntoIter<Self::Item>;
@@ -157,7 +204,7 @@ impl IntoIterator for Events {
 
 #[cfg(test)]
 mod tests {
-    use nostr::{JsonUtil, Kind};
+    use nostr::{EventBuilder, JsonUtil, Keys, Kind, Timestamp};
 
     use super::*;
 
@@ -272,4 +319,66 @@ mod tests {
         assert!(events.prev_not_match);
         assert_eq!(events.set.capacity(), Capacity::Unbounded);
     }
+
+    #[test]
+    fn test_retain() {
+        let keys_a = Keys::generate();
+        let keys_b = Keys::generate();
+
+        let mut events = Events::new(&Filter::new());
+        for i in 0..10 {
+            let keys: &Keys = if i % 2 == 0 { &keys_a } else { &keys_b };
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .custom_created_at(Timestamp::from_secs(1_700_000_000 + i))
+                .sign_with_keys(keys)
+                .unwrap();
+            events.insert(event);
+        }
+
+        // Drop events of a "blocked" author
+        events.retain(|e| e.pubkey != keys_b.public_key);
+        assert_eq!(events.len(), 5);
+
+        let contents: Vec<String> = events.clone().filter_map_into_vec(|e| Some(e.content));
+        assert_eq!(
+            contents,
+            vec!["Note 8", "Note 6", "Note 4", "Note 2", "Note 0"]
+        );
+
+        let timestamps: Vec<Timestamp> = events.iter().map(|e| e.created_at).collect();
+        assert!(timestamps.windows(2).all(|w| w[0] > w[1]));
+    }
+
+    #[test]
+    fn test_merge_overlapping() {
+        let keys = Keys::generate();
+        let base = Timestamp::from_secs(1_700_000_000);
+
+        let notes: Vec<Event> = (0..6)
+            .map(|i| {
+                EventBuilder::text_note(format!("Note {i}"))
+                    // Pairs of events share the same timestamp
+                    .custom_created_at(base + i / 2)
+                    .sign_with_keys(&keys)
+                    .unwrap()
+            })
+            .collect();
+
+        let filter = Filter::new().kind(Kind::TextNote);
+
+        let mut local = Events::new(&filter);
+        local.extend(notes[..4].iter().cloned());
+
+        let remote: Events = notes[2..].iter().cloned().collect();
+        assert_eq!(remote.len(), 4);
+
+        let events = local.merge(remote);
+        assert_eq!(events.len(), notes.len());
+        assert_eq!(events.set.capacity(), Capacity::Unbounded);
+
+        // Newest first, lowest ID first on identical timestamps
+        let mut expected: Vec<Event> = notes;
+        expected.sort();
+        assert_eq!(events.to_vec(), expected);
+    }
 }
@@ -3,4 +3,5 @@
 // Distributed under the MIT software license
 
 pub mod events;
+pub(crate) mod sketch;
 pub mod tree;
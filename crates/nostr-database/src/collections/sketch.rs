@@ -0,0 +1,128 @@
+// Copyright (c) 2022-2023 Yuki Kishimoto
+// Copyright (c) 2023-2025 Rust Nostr Developers
+// Distributed under the MIT software license
+
+//! Count-Min sketch
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Number of counters per row
+const WIDTH: usize = 2048;
+/// Number of rows
+const DEPTH: usize = 4;
+
+/// Count-Min sketch
+///
+/// Estimate the number of occurrences of a key. The estimate is never lower than the exact count
+/// and exceeds it by at most [`CountMinSketch::EPSILON`] times the total count,
+/// with probability at least `1 - e^-DEPTH` (~98%).
+///
+/// The counters are decremented on removal, so the bounds hold also after deletions,
+/// as long as only the previously added keys are removed.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CountMinSketch {
+    /// `DEPTH` rows of `WIDTH` counters, allocated at the first insertion
+    counters: Vec<u32>,
+}
+
+impl CountMinSketch {
+    /// Error of a single estimate, relative to the total count (`e / WIDTH`)
+    pub const EPSILON: f64 = std::f64::consts::E / WIDTH as f64;
+
+    /// Positions of the key counters, one per row
+    fn positions<K>(key: &K) -> impl Iterator<Item = usize>
+    where
+        K: Hash,
+    {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash: u64 = hasher.finish();
+
+        // Derive the row hashes from the two halves of the hash
+        let h1: usize = (hash & 0xFFFF_FFFF) as usize;
+        let h2: usize = (hash >> 32) as usize;
+
+        (0..DEPTH).map(move |row| row * WIDTH + h1.wrapping_add(row.wrapping_mul(h2)) % WIDTH)
+    }
+
+    pub fn insert<K>(&mut self, key: &K)
+    where
+        K: Hash,
+    {
+        if self.counters.is_empty() {
+            self.counters = vec![0; WIDTH * DEPTH];
+        }
+
+        for pos in Self::positions(key) {
+            self.counters[pos] = self.counters[pos].saturating_add(1);
+        }
+    }
+
+    pub fn remove<K>(&mut self, key: &K)
+    where
+        K: Hash,
+    {
+        if self.counters.is_empty() {
+            return;
+        }
+
+        for pos in Self::positions(key) {
+            self.counters[pos] = self.counters[pos].saturating_sub(1);
+        }
+    }
+
+    /// Estimate the number of occurrences of the key
+    pub fn estimate<K>(&self, key: &K) -> usize
+    where
+        K: Hash,
+    {
+        if self.counters.is_empty() {
+            return 0;
+        }
+
+        Self::positions(key)
+            .map(|pos| self.counters[pos] as usize)
+            .min()
+            .unwrap_or_default()
+    }
+
+    /// Approximate number of bytes used by the counters
+    #[inline]
+    pub fn memory_estimate(&self) -> usize {
+        self.counters.len() * std::mem::size_of::<u32>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        let mut sketch = CountMinSketch::default();
+        assert_eq!(sketch.estimate(&1u32), 0);
+
+        for key in 0..1_000u32 {
+            for _ in 0..(key % 5) {
+                sketch.insert(&key);
+            }
+        }
+
+        let total: usize = (0..1_000).map(|key| key % 5).sum();
+        let max_error: usize = (CountMinSketch::EPSILON * total as f64).ceil() as usize;
+
+        for key in 0..1_000u32 {
+            let exact: usize = (key % 5) as usize;
+            let estimate: usize = sketch.estimate(&key);
+            assert!(estimate >= exact);
+            assert!(estimate <= exact + max_error);
+        }
+
+        // Removal
+        for _ in 0..4 {
+            sketch.remove(&4u32);
+        }
+        assert!(sketch.estimate(&4u32) <= max_error);
+    }
+}
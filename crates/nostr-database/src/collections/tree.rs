@@ -286,6 +286,16 @@ where
     pub fn iter(&self) -> Iter<'_, T> {
         self.set.iter()
     }
+
+    /// Retain only the values specified by the predicate
+    #[inline]
+    pub fn retain<F>(&mut self, f: F)
+    where
+        T: Ord,
+        F: FnMut(&T) -> bool,
+    {
+        self.set.retain(f)
+    }
 }
 
 impl<T> From<BTreeSet<T>> for BTreeCappedSet<T> {
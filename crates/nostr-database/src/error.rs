@@ -8,11 +8,20 @@ use std::fmt;
 
 /// Database Error
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum DatabaseError {
     /// An error happened in the underlying database backend.
     Backend(Box<dyn std::error::Error + Send + Sync>),
     /// Not supported
     NotSupported,
+    /// Not found
+    NotFound,
+    /// The database is full
+    Full,
+    /// The stored data is corrupted
+    Corrupted,
+    /// The database is read-only
+    ReadOnly,
 }
 
 impl std::error::Error for DatabaseError {}
@@ -22,6 +31,10 @@ impl fmt::Display for DatabaseError {
         match self {
             Self::Backend(e) => write!(f, "{e}"),
             Self::NotSupported => write!(f, "not supported"),
+            Self::NotFound => write!(f, "not found"),
+            Self::Full => write!(f, "database full"),
+            Self::Corrupted => write!(f, "database corrupted"),
+            Self::ReadOnly => write!(f, "database is read-only"),
         }
     }
 }
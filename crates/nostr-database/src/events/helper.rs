@@ -6,17 +6,21 @@
 //!
 //! Used for the in-memory database.
 
-use std::collections::{BTreeSet, HashMap, HashSet};
-use std::iter;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::num::NonZeroUsize;
 use std::ops::Deref;
 use std::sync::Arc;
+use std::{iter, mem};
 
 use nostr::nips::nip01::{Coordinate, CoordinateBorrow};
 use nostr::{Alphabet, Event, EventId, Filter, Kind, PublicKey, SingleLetterTag, Timestamp};
 use tokio::sync::{OwnedRwLockReadGuard, RwLock};
 
+use crate::collections::sketch::CountMinSketch;
 use crate::collections::tree::{BTreeCappedSet, Capacity, InsertResult, OverCapacityPolicy};
-use crate::{Events, RejectedReason, SaveEventStatus};
+use crate::{match_event, Events, PruneReport, RejectedReason, SaveEventStatus};
 
 type DatabaseEvent = Arc<Event>;
 
@@ -75,6 +79,11 @@ enum QueryPattern {
 
 impl From<Filter> for QueryPattern {
     fn from(filter: Filter) -> Self {
+        // Multi-letter tags aren't indexed
+        if !filter.multi_letter_tags.is_empty() {
+            return Self::Generic(Box::new(filter));
+        }
+
         let (kinds_len, first_kind): (usize, Option<Kind>) = filter
             .kinds
             .as_ref()
@@ -140,11 +149,142 @@ pub struct DatabaseEventResult {
     pub to_discard: HashSet<EventId>,
 }
 
+/// Conflict resolution for replaceable/addressable events with identical timestamps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TieBreaker {
+    /// Retain the event with the lowest ID (NIP-01)
+    #[default]
+    LowestId,
+    /// Retain the event that was stored first
+    FirstSeen,
+}
+
+/// Check if the `stored` event takes precedence over the `new` one (same replaceable/addressable slot)
+///
+/// The newest event wins. In case of identical timestamps, the [`TieBreaker`] decides.
+#[inline]
+fn has_precedence(stored: &Event, new: &Event, tie_breaker: TieBreaker) -> bool {
+    match stored.created_at.cmp(&new.created_at) {
+        Ordering::Greater => true,
+        Ordering::Less => false,
+        Ordering::Equal => match tie_breaker {
+            TieBreaker::LowestId => stored.id <= new.id,
+            TieBreaker::FirstSeen => true,
+        },
+    }
+}
+
 enum InternalQueryResult<'a> {
     All,
     Set(BTreeSet<&'a DatabaseEvent>),
 }
 
+/// Author, kind and timestamp of a deleted event that was stored
+#[derive(Debug, Clone, Copy)]
+struct Tombstone {
+    author: PublicKey,
+    kind: Kind,
+    created_at: Timestamp,
+}
+
+impl Tombstone {
+    #[inline]
+    fn new(event: &Event) -> Self {
+        Self {
+            author: event.pubkey,
+            kind: event.kind,
+            created_at: event.created_at,
+        }
+    }
+
+    /// Check if the deleted event matched the filter
+    ///
+    /// The tags and the content aren't tracked: a filter with tag or search conditions never matches.
+    fn match_filter(&self, filter: &Filter) -> bool {
+        filter.generic_tags.is_empty()
+            && filter.search.is_none()
+            && filter
+                .authors
+                .as_ref()
+                .map_or(true, |authors| authors.contains(&self.author))
+            && filter
+                .kinds
+                .as_ref()
+                .map_or(true, |kinds| kinds.contains(&self.kind))
+            && filter.since.map_or(true, |since| self.created_at >= since)
+            && filter.until.map_or(true, |until| self.created_at <= until)
+    }
+}
+
+/// Deleted event IDs
+///
+/// If capped, the oldest records are evicted first.
+#[derive(Debug, Clone, Default)]
+struct DeletedIds {
+    /// The tombstone is tracked only if the deleted event was stored
+    ids: HashMap<EventId, Option<Tombstone>>,
+    /// Insertion order, tracked only if capped
+    order: VecDeque<EventId>,
+    max: Option<NonZeroUsize>,
+}
+
+impl DeletedIds {
+    #[inline]
+    fn contains(&self, id: &EventId) -> bool {
+        self.ids.contains_key(id)
+    }
+
+    fn insert(&mut self, id: EventId, tombstone: Option<Tombstone>) {
+        let is_new: bool = match self.ids.get_mut(&id) {
+            Some(current) => {
+                if tombstone.is_some() {
+                    *current = tombstone;
+                }
+                false
+            }
+            None => {
+                self.ids.insert(id, tombstone);
+                true
+            }
+        };
+
+        if let Some(max) = self.max {
+            if is_new {
+                self.order.push_back(id);
+            }
+
+            while self.ids.len() > max.get() {
+                match self.order.pop_front() {
+                    Some(oldest) => {
+                        self.ids.remove(&oldest);
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, id: &EventId) {
+        if self.ids.remove(id).is_some() && self.max.is_some() {
+            self.order.retain(|i| i != id);
+        }
+    }
+
+    /// Remove the IDs of the deleted events that matched the filter
+    fn remove_matching(&mut self, filter: &Filter) {
+        let ids: Vec<EventId> = self
+            .ids
+            .iter()
+            .filter(|(_, tombstone)| tombstone.is_some_and(|t| t.match_filter(filter)))
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in ids.iter() {
+            self.remove(id);
+        }
+    }
+}
+
 /// Database helper
 #[derive(Debug, Clone, Default)]
 struct InternalDatabaseHelper {
@@ -155,7 +295,15 @@ struct InternalDatabaseHelper {
     author_index: HashMap<PublicKey, BTreeSet<DatabaseEvent>>,
     kind_author_index: HashMap<(Kind, PublicKey), BTreeSet<DatabaseEvent>>,
     param_replaceable_index: HashMap<(Kind, PublicKey, String), DatabaseEvent>,
-    deleted_ids: HashSet<EventId>,
+    /// Single-letter tags to index (opt-in)
+    indexed_tags: HashSet<SingleLetterTag>,
+    tag_index: HashMap<(SingleLetterTag, String), BTreeSet<DatabaseEvent>>,
+    /// Approximate number of events per author
+    author_sketch: CountMinSketch,
+    /// Approximate number of events per kind
+    kind_sketch: CountMinSketch,
+    tie_breaker: TieBreaker,
+    deleted_ids: DeletedIds,
     deleted_coordinates: HashMap<Coordinate, Timestamp>,
 }
 
@@ -169,6 +317,13 @@ impl InternalDatabaseHelper {
         helper
     }
 
+    fn index_tags<I>(&mut self, tags: I)
+    where
+        I: IntoIterator<Item = SingleLetterTag>,
+    {
+        self.indexed_tags.extend(tags);
+    }
+
     // Bulk load
     //
     // NOT CHANGE `events` ARG! Processing events in ASC it's much more performant
@@ -178,7 +333,7 @@ impl InternalDatabaseHelper {
             .into_iter()
             .rev() // Lookup ID: EVENT_ORD_IMPL
             .filter(|e| !e.kind.is_ephemeral())
-            .map(|event| self.internal_index_event(&event, &now))
+            .map(|event| self.internal_index_event(&event, &now, self.tie_breaker))
             .flat_map(|res| res.to_discard)
             .collect()
     }
@@ -190,10 +345,19 @@ impl InternalDatabaseHelper {
             .into_iter()
             .rev() // Lookup ID: EVENT_ORD_IMPL
             .filter(|e| !e.is_expired() && !e.kind.is_ephemeral())
-            .filter(move |event| self.internal_index_event(event, &now).status.is_success())
+            .filter(move |event| {
+                self.internal_index_event(event, &now, self.tie_breaker)
+                    .status
+                    .is_success()
+            })
     }
 
-    fn internal_index_event(&mut self, event: &Event, now: &Timestamp) -> DatabaseEventResult {
+    fn internal_index_event(
+        &mut self,
+        event: &Event,
+        now: &Timestamp,
+        tie_breaker: TieBreaker,
+    ) -> DatabaseEventResult {
         // Check if was already added
         if self.ids.contains_key(&event.id) {
             return DatabaseEventResult {
@@ -233,7 +397,7 @@ impl InternalDatabaseHelper {
         if kind.is_replaceable() {
             let params: QueryByKindAndAuthorParams = QueryByKindAndAuthorParams::new(kind, author);
             for ev in self.internal_query_by_kind_and_author(params) {
-                if ev.created_at > created_at || ev.id == event.id {
+                if has_precedence(ev, event, tie_breaker) {
                     status = SaveEventStatus::Rejected(RejectedReason::Replaced);
                 } else {
                     to_discard.insert(ev.id);
@@ -252,7 +416,7 @@ impl InternalDatabaseHelper {
                         let params: QueryByParamReplaceable =
                             QueryByParamReplaceable::new(kind, author, identifier.to_string());
                         if let Some(ev) = self.internal_query_param_replaceable(params) {
-                            if ev.created_at > created_at || ev.id == event.id {
+                            if has_precedence(ev, event, tie_breaker) {
                                 status = SaveEventStatus::Rejected(RejectedReason::Replaced);
                             } else {
                                 to_discard.insert(ev.id);
@@ -329,6 +493,8 @@ impl InternalDatabaseHelper {
 
             if inserted {
                 self.ids.insert(e.id, e.clone());
+                self.author_sketch.insert(&author);
+                self.kind_sketch.insert(&kind);
                 self.author_index
                     .entry(author)
                     .or_default()
@@ -341,6 +507,17 @@ impl InternalDatabaseHelper {
                     }
                 }
 
+                for (tag, values) in e.tags.indexes().iter() {
+                    if self.indexed_tags.contains(tag) {
+                        for value in values.iter() {
+                            self.tag_index
+                                .entry((*tag, value.clone()))
+                                .or_default()
+                                .insert(e.clone());
+                        }
+                    }
+                }
+
                 if kind.is_replaceable() {
                     let mut set = BTreeSet::new();
                     set.insert(e);
@@ -364,9 +541,54 @@ impl InternalDatabaseHelper {
         DatabaseEventResult { status, to_discard }
     }
 
+    /// Remove the events superseded by a newer replaceable or addressable version,
+    /// or targeted by a stored deletion (NIP-09)
+    ///
+    /// The indexes are kept tight while indexing, so nothing is expected to be removed:
+    /// this validates them.
+    fn prune(&mut self) -> PruneReport {
+        let mut report: PruneReport = PruneReport::default();
+        let mut live: HashSet<Coordinate> = HashSet::new();
+        let mut to_discard: HashSet<EventId> = HashSet::new();
+
+        // Newest first and, on ties, lowest ID first:
+        // the first event of every coordinate is the live one.
+        // Lookup ID: EVENT_ORD_IMPL
+        for event in self.events.iter() {
+            if self.deleted_ids.contains(&event.id) {
+                to_discard.insert(event.id);
+                report.deleted += 1;
+                continue;
+            }
+
+            if let Some(coordinate) = event.coordinate() {
+                let coordinate: Coordinate = coordinate.into_owned();
+
+                if self.has_coordinate_been_deleted(&coordinate, &event.created_at) {
+                    to_discard.insert(event.id);
+                    report.deleted += 1;
+                } else if !live.insert(coordinate) {
+                    to_discard.insert(event.id);
+                    report.replaced += 1;
+                }
+            }
+        }
+
+        self.discard_events(&to_discard);
+
+        report
+    }
+
     fn discard_events(&mut self, ids: &HashSet<EventId>) {
         for id in ids.iter() {
+            let mut tombstone: Option<Tombstone> = None;
+
             if let Some(ev) = self.ids.remove(id) {
+                tombstone = Some(Tombstone::new(&ev));
+
+                self.author_sketch.remove(&ev.pubkey);
+                self.kind_sketch.remove(&ev.kind);
+
                 self.events.remove(&ev);
 
                 if let Some(set) = self.author_index.get_mut(&ev.pubkey) {
@@ -386,13 +608,18 @@ impl InternalDatabaseHelper {
                 if let Some(set) = self.kind_author_index.get_mut(&(ev.kind, ev.pubkey)) {
                     set.remove(&ev);
                 }
+
+                self.remove_from_tag_index(&ev);
             }
-            self.deleted_ids.insert(*id);
+            self.deleted_ids.insert(*id, tombstone);
         }
     }
 
     fn discard_event(&mut self, ev: DatabaseEvent) {
-        self.ids.remove(&ev.id);
+        if self.ids.remove(&ev.id).is_some() {
+            self.author_sketch.remove(&ev.pubkey);
+            self.kind_sketch.remove(&ev.kind);
+        }
 
         if let Some(set) = self.author_index.get_mut(&ev.pubkey) {
             set.remove(&ev);
@@ -408,12 +635,38 @@ impl InternalDatabaseHelper {
         if let Some(set) = self.kind_author_index.get_mut(&(ev.kind, ev.pubkey)) {
             set.remove(&ev);
         }
+
+        self.remove_from_tag_index(&ev);
+    }
+
+    fn remove_from_tag_index(&mut self, ev: &DatabaseEvent) {
+        if self.indexed_tags.is_empty() {
+            return;
+        }
+
+        for (tag, values) in ev.tags.indexes().iter() {
+            if self.indexed_tags.contains(tag) {
+                for value in values.iter() {
+                    if let Some(set) = self.tag_index.get_mut(&(*tag, value.clone())) {
+                        set.remove(ev);
+                    }
+                }
+            }
+        }
     }
 
     /// Import [Event]
     ///
     /// **This method assume that [`Event`] was already verified**
     pub fn index_event(&mut self, event: &Event) -> DatabaseEventResult {
+        self.index_event_with_tie_breaker(event, self.tie_breaker)
+    }
+
+    fn index_event_with_tie_breaker(
+        &mut self,
+        event: &Event,
+        tie_breaker: TieBreaker,
+    ) -> DatabaseEventResult {
         // Check if it's ephemeral
         if event.kind.is_ephemeral() {
             return DatabaseEventResult {
@@ -422,7 +675,14 @@ impl InternalDatabaseHelper {
             };
         }
         let now = Timestamp::now();
-        self.internal_index_event(event, &now)
+        self.internal_index_event(event, &now, tie_breaker)
+    }
+
+    /// Import [Event] only if newer than the stored replaceable or addressable event
+    ///
+    /// On timestamp ties, the stored event is kept, regardless of the [`TieBreaker`].
+    pub fn replace_if_newer(&mut self, event: &Event) -> DatabaseEventResult {
+        self.index_event_with_tie_breaker(event, TieBreaker::FirstSeen)
     }
 
     /// Query by public key
@@ -534,15 +794,51 @@ impl InternalDatabaseHelper {
         Some(ev)
     }
 
+    /// Get the candidates from the ID index, if the filter constrains the IDs
+    fn internal_query_ids_candidates(&self, filter: &Filter) -> Option<BTreeSet<&DatabaseEvent>> {
+        let ids = filter.ids.as_ref().filter(|ids| !ids.is_empty())?;
+
+        // Lookup ID: EVENT_ORD_IMPL
+        Some(ids.iter().filter_map(|id| self.ids.get(id)).collect())
+    }
+
+    /// Get the candidates from the tag index, if the filter constrains an indexed tag
+    fn internal_query_tag_index_candidates(
+        &self,
+        filter: &Filter,
+    ) -> Option<BTreeSet<&DatabaseEvent>> {
+        let (tag, values) = filter
+            .generic_tags
+            .iter()
+            .find(|(tag, _)| self.indexed_tags.contains(tag))?;
+
+        let mut candidates: BTreeSet<&DatabaseEvent> = BTreeSet::new();
+        for value in values.iter() {
+            if let Some(set) = self.tag_index.get(&(*tag, value.clone())) {
+                candidates.extend(set.iter());
+            }
+        }
+
+        Some(candidates)
+    }
+
     /// Generic query
     #[inline]
     fn internal_generic_query(&self, filter: Filter) -> impl Iterator<Item = &DatabaseEvent> {
-        self.events
-            .iter()
-            .filter(move |event| !self.deleted_ids.contains(&event.id) && filter.match_event(event))
+        self.events.iter().filter(move |event| {
+            !self.deleted_ids.contains(&event.id) && match_event(&filter, event)
+        })
     }
 
     fn internal_query(&self, filter: Filter) -> InternalQueryResult {
+        // A tag with no values matches no event
+        if filter.has_empty_tags() {
+            return InternalQueryResult::Set(BTreeSet::new());
+        }
+
+        // Drop the empty lists, so they aren't used for the index lookups
+        let filter: Filter = filter.normalized();
+
         if filter.is_empty() {
             return InternalQueryResult::All;
         }
@@ -565,7 +861,19 @@ impl InternalDatabaseHelper {
                     None => Box::new(iter::empty()),
                 }
             }
-            QueryPattern::Generic(filter) => Box::new(self.internal_generic_query(*filter)),
+            QueryPattern::Generic(filter) => {
+                // Prefer the ID index, since it's the most selective
+                let candidates: Option<BTreeSet<&DatabaseEvent>> = self
+                    .internal_query_ids_candidates(&filter)
+                    .or_else(|| self.internal_query_tag_index_candidates(&filter));
+
+                match candidates {
+                    Some(candidates) => Box::new(candidates.into_iter().filter(move |ev| {
+                        !self.deleted_ids.contains(&ev.id) && match_event(&filter, ev)
+                    })),
+                    None => Box::new(self.internal_generic_query(*filter)),
+                }
+            }
         };
 
         if let Some(limit) = limit {
@@ -595,7 +903,26 @@ impl InternalDatabaseHelper {
         }
     }
 
+    /// Query in ascending order (oldest first)
+    pub fn query_asc<'a>(&'a self, mut filter: Filter) -> Box<dyn Iterator<Item = &'a Event> + 'a> {
+        // The limit must be applied after reversing the order
+        let limit: Option<usize> = filter.limit.take();
+
+        // Lookup ID: EVENT_ORD_IMPL
+        let iter: Box<dyn Iterator<Item = &DatabaseEvent>> = match self.internal_query(filter) {
+            InternalQueryResult::All => Box::new(self.events.iter().rev()),
+            InternalQueryResult::Set(set) => Box::new(set.into_iter().rev()),
+        };
+
+        match limit {
+            Some(limit) => Box::new(iter.take(limit).map(|ev| ev.as_ref())),
+            None => Box::new(iter.map(|ev| ev.as_ref())),
+        }
+    }
+
     /// Count events
+    ///
+    /// Capped at the filter `limit`, if any: the query stops once the limit is reached.
     pub fn count(&self, filter: Filter) -> usize {
         match self.internal_query(filter) {
             InternalQueryResult::All => self.events.len(),
@@ -603,6 +930,84 @@ impl InternalDatabaseHelper {
         }
     }
 
+    /// Approximate count of the events
+    ///
+    /// See [`DatabaseHelper::count_approx`].
+    pub fn count_approx(&self, filter: Filter, error_rate: f64) -> usize {
+        let only_authors_or_kinds: bool = filter.ids.is_none()
+            && filter.search.is_none()
+            && filter.since.is_none()
+            && filter.until.is_none()
+            && filter.limit.is_none()
+            && filter.generic_tags.is_empty();
+
+        if only_authors_or_kinds {
+            let estimate: Option<usize> = match (&filter.authors, &filter.kinds) {
+                (None, None) => Some(self.events.len()),
+                (Some(authors), None) => {
+                    self.sketch_count(&self.author_sketch, authors, error_rate)
+                }
+                (None, Some(kinds)) => self.sketch_count(&self.kind_sketch, kinds, error_rate),
+                (Some(..), Some(..)) => None,
+            };
+
+            if let Some(estimate) = estimate {
+                return estimate;
+            }
+        }
+
+        self.count(filter)
+    }
+
+    /// Sum the estimates of the keys
+    ///
+    /// Return `None` if the sketch can't guarantee the requested error rate.
+    fn sketch_count<K>(
+        &self,
+        sketch: &CountMinSketch,
+        keys: &BTreeSet<K>,
+        error_rate: f64,
+    ) -> Option<usize>
+    where
+        K: Hash,
+    {
+        // The errors of the single estimates add up
+        if keys.len() as f64 * CountMinSketch::EPSILON > error_rate {
+            return None;
+        }
+
+        let sum: usize = keys.iter().map(|key| sketch.estimate(key)).sum();
+        Some(sum.min(self.events.len()))
+    }
+
+    /// Get the newest event matching the filter
+    pub fn latest(&self, filter: Filter) -> Option<(EventId, Timestamp)> {
+        // Lookup ID: EVENT_ORD_IMPL
+        let ev: &DatabaseEvent = match self.internal_query(filter.limit(1)) {
+            InternalQueryResult::All => self.events.iter().next()?,
+            InternalQueryResult::Set(set) => set.into_iter().next()?,
+        };
+        Some((ev.id, ev.created_at))
+    }
+
+    /// Count the union of events matching the filters
+    pub fn count_by_filters(&self, filters: Vec<Filter>) -> usize {
+        let mut ids: HashSet<EventId> = HashSet::new();
+
+        for filter in filters.into_iter() {
+            match self.internal_query(filter) {
+                InternalQueryResult::All => return self.events.len(),
+                InternalQueryResult::Set(set) => ids.extend(set.into_iter().map(|ev| ev.id)),
+            }
+        }
+
+        ids.len()
+    }
+
+    pub fn event_ids(&self, filter: Filter) -> BTreeSet<EventId> {
+        self.query(filter).map(|ev| ev.id).collect()
+    }
+
     pub fn negentropy_items(&self, filter: Filter) -> Vec<(EventId, Timestamp)> {
         match self.internal_query(filter) {
             InternalQueryResult::All => self
@@ -634,29 +1039,218 @@ impl InternalDatabaseHelper {
         }
     }
 
-    pub fn delete(&mut self, filter: Filter) -> Option<HashSet<EventId>> {
+    /// Remove the events matching the filter
+    ///
+    /// If `tombstone` is `true`, the IDs are marked as deleted, so the events can't be saved again.
+    pub fn delete(&mut self, filter: Filter, tombstone: bool) -> Option<HashSet<EventId>> {
         match self.internal_query(filter) {
             InternalQueryResult::All => {
+                let tombstones: Vec<(EventId, Tombstone)> = if tombstone {
+                    self.ids
+                        .values()
+                        .map(|ev| (ev.id, Tombstone::new(ev)))
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+
                 self.clear();
+
+                for (id, tombstone) in tombstones.into_iter() {
+                    self.deleted_ids.insert(id, Some(tombstone));
+                }
+
                 None
             }
             InternalQueryResult::Set(set) => {
-                let ids: HashSet<EventId> = set.into_iter().map(|ev| ev.id).collect();
-                self.discard_events(&ids);
+                let events: Vec<DatabaseEvent> = set.into_iter().cloned().collect();
+                let ids: HashSet<EventId> = events.iter().map(|ev| ev.id).collect();
+
+                if tombstone {
+                    self.discard_events(&ids);
+                } else {
+                    for ev in events.into_iter() {
+                        self.events.remove(&ev);
+                        self.discard_event(ev);
+                    }
+                }
+
                 Some(ids)
             }
         }
     }
 
+    /// Index again the discarded events, forgetting their deletion
+    pub fn restore<I>(&mut self, events: I)
+    where
+        I: IntoIterator<Item = Event>,
+    {
+        let now: Timestamp = Timestamp::now();
+        for event in events.into_iter() {
+            self.deleted_ids.remove(&event.id);
+            self.internal_index_event(&event, &now, self.tie_breaker);
+        }
+    }
+
+    /// Remove the events matching the filter and forget their deletion bookkeeping
+    ///
+    /// Return the number of removed events.
+    pub fn wipe_filter(&mut self, filter: Filter) -> usize {
+        let ids: Vec<EventId> = filter.ids.iter().flatten().copied().collect();
+        let authors: Option<BTreeSet<PublicKey>> = filter.authors.clone();
+        let kinds: Option<BTreeSet<Kind>> = filter.kinds.clone();
+
+        // Forget the deleted events matching the filter, also the replaced and evicted ones
+        self.deleted_ids.remove_matching(&filter);
+
+        let events: Vec<DatabaseEvent> = match self.internal_query(filter) {
+            InternalQueryResult::All => {
+                let len: usize = self.events.len();
+                self.clear();
+                return len;
+            }
+            InternalQueryResult::Set(set) => set.into_iter().cloned().collect(),
+        };
+
+        for ev in events.iter() {
+            // Forget the deletions requested by the removed event
+            if ev.kind == Kind::EventDeletion {
+                for id in ev.tags.event_ids() {
+                    self.deleted_ids.remove(id);
+                }
+
+                for coordinate in ev.tags.coordinates() {
+                    self.deleted_coordinates.remove(coordinate);
+                }
+            }
+
+            // Remove without marking as deleted
+            self.events.remove(ev);
+            self.discard_event(ev.clone());
+        }
+
+        // Forget the explicitly requested IDs
+        for id in ids.iter() {
+            self.deleted_ids.remove(id);
+        }
+
+        // Forget the deleted coordinates matching the filter
+        if authors.is_some() || kinds.is_some() {
+            self.deleted_coordinates.retain(|coordinate, _| {
+                let author_match: bool = authors
+                    .as_ref()
+                    .map_or(true, |authors| authors.contains(&coordinate.public_key));
+                let kind_match: bool = kinds
+                    .as_ref()
+                    .map_or(true, |kinds| kinds.contains(&coordinate.kind));
+                !(author_match && kind_match)
+            });
+        }
+
+        events.len()
+    }
+
+    /// Remove all the events except the `keep_newest` ones
+    ///
+    /// Return the number of removed events.
+    pub fn truncate_to(&mut self, keep_newest: usize) -> usize {
+        // Lookup ID: EVENT_ORD_IMPL
+        let events: Vec<DatabaseEvent> = self.events.iter().skip(keep_newest).cloned().collect();
+
+        for ev in events.iter() {
+            // Remove without marking as deleted
+            self.events.remove(ev);
+            self.discard_event(ev.clone());
+        }
+
+        events.len()
+    }
+
+    /// Approximate number of bytes used by the indexed events and by the indexes
+    ///
+    /// Allocator and hash table overheads aren't included.
+    pub fn memory_estimate(&self) -> usize {
+        let ptr: usize = mem::size_of::<DatabaseEvent>();
+
+        // Events
+        let events: usize = self
+            .events
+            .iter()
+            .map(|e| {
+                mem::size_of::<Event>()
+                    + e.content.len()
+                    + e.tags
+                        .iter()
+                        .map(|t| t.as_slice().iter().map(|s| s.len()).sum::<usize>())
+                        .sum::<usize>()
+            })
+            .sum::<usize>()
+            + self.events.len() * ptr;
+
+        // Indexes
+        let ids: usize = self.ids.len() * (mem::size_of::<EventId>() + ptr);
+        let author_index: usize = self
+            .author_index
+            .values()
+            .map(|set| mem::size_of::<PublicKey>() + set.len() * ptr)
+            .sum();
+        let kind_author_index: usize = self
+            .kind_author_index
+            .values()
+            .map(|set| mem::size_of::<(Kind, PublicKey)>() + set.len() * ptr)
+            .sum();
+        let param_replaceable_index: usize = self
+            .param_replaceable_index
+            .keys()
+            .map(|(_, _, identifier)| {
+                mem::size_of::<(Kind, PublicKey, String)>() + identifier.len() + ptr
+            })
+            .sum();
+        let tag_index: usize = self
+            .tag_index
+            .iter()
+            .map(|((_, value), set)| {
+                mem::size_of::<(SingleLetterTag, String)>() + value.len() + set.len() * ptr
+            })
+            .sum();
+        let sketches: usize =
+            self.author_sketch.memory_estimate() + self.kind_sketch.memory_estimate();
+
+        // Deletions
+        let deleted_ids: usize =
+            self.deleted_ids.ids.len() * mem::size_of::<(EventId, Option<Tombstone>)>();
+        let deleted_coordinates: usize = self
+            .deleted_coordinates
+            .keys()
+            .map(|c| mem::size_of::<(Coordinate, Timestamp)>() + c.identifier.len())
+            .sum();
+
+        events
+            + ids
+            + author_index
+            + kind_author_index
+            + param_replaceable_index
+            + tag_index
+            + sketches
+            + deleted_ids
+            + deleted_coordinates
+    }
+
     pub fn clear(&mut self) {
-        // Get current capacity
+        // Get current capacity, indexed tags, tie breaker and deletion records cap
         let capacity: Capacity = self.events.capacity();
+        let indexed_tags: HashSet<SingleLetterTag> = mem::take(&mut self.indexed_tags);
+        let tie_breaker: TieBreaker = self.tie_breaker;
+        let max_deletion_records: Option<NonZeroUsize> = self.deleted_ids.max;
 
         // Reset helper to default
         *self = Self::default();
 
-        // Change capacity
+        // Change capacity and restore indexed tags, tie breaker and deletion records cap
         self.events.change_capacity(capacity);
+        self.indexed_tags = indexed_tags;
+        self.tie_breaker = tie_breaker;
+        self.deleted_ids.max = max_deletion_records;
     }
 }
 
@@ -686,6 +1280,43 @@ impl DatabaseHelper {
         }
     }
 
+    /// Database helper that maintains a secondary index for the provided single-letter tags
+    ///
+    /// Queries constraining an indexed tag avoid scanning all the events.
+    /// If `max` is `None`, the helper is unbounded.
+    #[inline]
+    pub fn with_indexed_tags<I>(max: Option<usize>, tags: I) -> Self
+    where
+        I: IntoIterator<Item = SingleLetterTag>,
+    {
+        Self::custom(max, tags, TieBreaker::default(), None)
+    }
+
+    /// Custom database helper
+    ///
+    /// If `max` is `None`, the helper is unbounded.
+    /// If `max_deletion_records` is `None`, the deleted IDs are remembered forever.
+    pub fn custom<I>(
+        max: Option<usize>,
+        tags: I,
+        tie_breaker: TieBreaker,
+        max_deletion_records: Option<NonZeroUsize>,
+    ) -> Self
+    where
+        I: IntoIterator<Item = SingleLetterTag>,
+    {
+        let mut inner: InternalDatabaseHelper = match max {
+            Some(max) => InternalDatabaseHelper::bounded(max),
+            None => InternalDatabaseHelper::default(),
+        };
+        inner.index_tags(tags);
+        inner.tie_breaker = tie_breaker;
+        inner.deleted_ids.max = max_deletion_records;
+        Self {
+            inner: Arc::new(RwLock::new(inner)),
+        }
+    }
+
     /// Query transaction
     #[inline]
     pub async fn qtxn(&self) -> QueryTransaction {
@@ -711,9 +1342,31 @@ impl DatabaseHelper {
     /// Index [`Event`]
     ///
     /// **This method assumes that [`Event`] was already verified**
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "save_event",
+            level = "trace",
+            skip_all,
+            fields(id = %event.id, kind = event.kind.as_u16(), status = tracing::field::Empty)
+        )
+    )]
     pub async fn index_event(&self, event: &Event) -> DatabaseEventResult {
         let mut inner = self.inner.write().await;
-        inner.index_event(event)
+        let res: DatabaseEventResult = inner.index_event(event);
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("status", tracing::field::debug(&res.status));
+        res
+    }
+
+    /// Index [`Event`] only if newer than the stored replaceable or addressable event
+    ///
+    /// The check and the indexing are performed under the same write lock.
+    ///
+    /// **This method assumes that [`Event`] was already verified**
+    pub async fn replace_if_newer(&self, event: &Event) -> DatabaseEventResult {
+        let mut inner = self.inner.write().await;
+        inner.replace_if_newer(event)
     }
 
     /// Get [Event] by ID
@@ -722,6 +1375,16 @@ impl DatabaseHelper {
         inner.event_by_id(id).cloned()
     }
 
+    /// Get [Event]s by IDs
+    ///
+    /// The results are positional: `None` for the missing or deleted events.
+    pub async fn event_by_ids(&self, ids: &[EventId]) -> Vec<Option<Event>> {
+        let inner = self.inner.read().await;
+        ids.iter()
+            .map(|id| inner.event_by_id(id).cloned())
+            .collect()
+    }
+
     /// Check if event exists
     pub async fn has_event(&self, id: &EventId) -> bool {
         let inner = self.inner.read().await;
@@ -729,13 +1392,54 @@ impl DatabaseHelper {
     }
 
     /// Query
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "query",
+            level = "trace",
+            skip_all,
+            fields(
+                ids = filter.ids.as_ref().map_or(0, |ids| ids.len()),
+                authors = filter.authors.as_ref().map_or(0, |authors| authors.len()),
+                kinds = filter.kinds.as_ref().map_or(0, |kinds| kinds.len()),
+                limit = ?filter.limit,
+                results = tracing::field::Empty,
+            )
+        )
+    )]
     pub async fn query(&self, filter: Filter) -> Events {
         let inner = self.inner.read().await;
         let mut events = Events::new(&filter);
         events.extend(inner.query(filter).cloned());
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("results", events.len());
         events
     }
 
+    /// Query, keeping only the events whose author matches the predicate
+    ///
+    /// The predicate is applied before cloning the events, and the `limit` after it.
+    pub async fn query_filtered<F>(&self, mut filter: Filter, keep: F) -> Events
+    where
+        F: Fn(&PublicKey) -> bool,
+    {
+        let inner = self.inner.read().await;
+        let mut events = Events::new(&filter);
+        let limit: Option<usize> = filter.limit.take();
+        let iter = inner.query(filter).filter(|e| keep(&e.pubkey));
+        match limit {
+            Some(limit) => events.extend(iter.take(limit).cloned()),
+            None => events.extend(iter.cloned()),
+        }
+        events
+    }
+
+    /// Query in ascending order (oldest first)
+    pub async fn query_asc(&self, filter: Filter) -> Vec<Event> {
+        let inner = self.inner.read().await;
+        inner.query_asc(filter).cloned().collect()
+    }
+
     /// Query
     pub fn fast_query<'a>(
         &self,
@@ -745,10 +1449,64 @@ impl DatabaseHelper {
         txn.guard.query(filter)
     }
 
-    /// Count events
+    /// Approximate number of bytes used by the in-memory events and indexes
+    pub async fn memory_estimate(&self) -> usize {
+        let inner = self.inner.read().await;
+        inner.memory_estimate()
+    }
+
+    /// Count events, capped at the filter `limit`
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "count",
+            level = "trace",
+            skip_all,
+            fields(
+                ids = filter.ids.as_ref().map_or(0, |ids| ids.len()),
+                authors = filter.authors.as_ref().map_or(0, |authors| authors.len()),
+                kinds = filter.kinds.as_ref().map_or(0, |kinds| kinds.len()),
+                limit = ?filter.limit,
+                results = tracing::field::Empty,
+            )
+        )
+    )]
     pub async fn count(&self, filter: Filter) -> usize {
         let inner = self.inner.read().await;
-        inner.count(filter)
+        let count: usize = inner.count(filter);
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("results", count);
+        count
+    }
+
+    /// Approximate count of the events matching the [`Filter`]
+    ///
+    /// The unfiltered counts are exact. The author-only and kind-only counts are estimated
+    /// with a Count-Min sketch, maintained on every insertion and removal: the estimate is never
+    /// lower than the exact count and exceeds it by at most `error_rate` times the number of
+    /// stored events, with probability at least 98%. The other filters, and the author or kind sets
+    /// too large for the requested `error_rate`, fall back to the exact [`DatabaseHelper::count`].
+    pub async fn count_approx(&self, filter: Filter, error_rate: f64) -> usize {
+        let inner = self.inner.read().await;
+        inner.count_approx(filter, error_rate)
+    }
+
+    /// Get the newest event matching the filter
+    pub async fn latest(&self, filter: Filter) -> Option<(EventId, Timestamp)> {
+        let inner = self.inner.read().await;
+        inner.latest(filter)
+    }
+
+    /// Count the union of events matching the filters
+    pub async fn count_by_filters(&self, filters: Vec<Filter>) -> usize {
+        let inner = self.inner.read().await;
+        inner.count_by_filters(filters)
+    }
+
+    /// Get the IDs of the events matching the filter
+    pub async fn event_ids(&self, filter: Filter) -> BTreeSet<EventId> {
+        let inner = self.inner.read().await;
+        inner.event_ids(filter)
     }
 
     /// Get negentropy items
@@ -775,10 +1533,89 @@ impl DatabaseHelper {
 
     /// Delete all events that match [Filter]
     ///
+    /// The events aren't marked as deleted, so they can be saved again
+    /// (see [`DatabaseHelper::delete_with_tombstone`]).
+    ///
     /// If return `None`, means that all events must be deleted from DB
     pub async fn delete(&self, filter: Filter) -> Option<HashSet<EventId>> {
         let mut inner = self.inner.write().await;
-        inner.delete(filter)
+        inner.delete(filter, false)
+    }
+
+    /// Delete all events that match [Filter] and return the number of deleted events
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "delete",
+            level = "trace",
+            skip_all,
+            fields(
+                ids = filter.ids.as_ref().map_or(0, |ids| ids.len()),
+                authors = filter.authors.as_ref().map_or(0, |authors| authors.len()),
+                kinds = filter.kinds.as_ref().map_or(0, |kinds| kinds.len()),
+                limit = ?filter.limit,
+                results = tracing::field::Empty,
+            )
+        )
+    )]
+    pub async fn delete_count(&self, filter: Filter) -> usize {
+        let mut inner = self.inner.write().await;
+        let len: usize = inner.events.len();
+        let deleted: usize = match inner.delete(filter, false) {
+            Some(ids) => ids.len(),
+            None => len,
+        };
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("results", deleted);
+        deleted
+    }
+
+    /// Remove the events superseded by a newer replaceable or addressable version,
+    /// or targeted by a stored deletion (NIP-09)
+    pub async fn prune(&self) -> PruneReport {
+        let mut inner = self.inner.write().await;
+        inner.prune()
+    }
+
+    /// Delete all events that match [Filter], marking them as deleted
+    ///
+    /// Unlike [`DatabaseHelper::delete`], the events can't be saved again.
+    ///
+    /// Return the number of deleted events.
+    pub async fn delete_with_tombstone(&self, filter: Filter) -> usize {
+        let mut inner = self.inner.write().await;
+        let len: usize = inner.events.len();
+        match inner.delete(filter, true) {
+            Some(ids) => ids.len(),
+            None => len,
+        }
+    }
+
+    /// Index again the events discarded by [`DatabaseHelper::index_event`], forgetting their deletion
+    ///
+    /// Used to roll back the indexing when the event can't be persisted by the backend.
+    pub async fn restore<I>(&self, events: I)
+    where
+        I: IntoIterator<Item = Event>,
+    {
+        let mut inner = self.inner.write().await;
+        inner.restore(events)
+    }
+
+    /// Remove the events matching the filter and forget their deletion bookkeeping
+    ///
+    /// Return the number of removed events.
+    pub async fn wipe_filter(&self, filter: Filter) -> usize {
+        let mut inner = self.inner.write().await;
+        inner.wipe_filter(filter)
+    }
+
+    /// Remove all the events except the `keep_newest` ones
+    ///
+    /// Return the number of removed events.
+    pub async fn truncate_to(&self, keep_newest: usize) -> usize {
+        let mut inner = self.inner.write().await;
+        inner.truncate_to(keep_newest)
     }
 
     /// Clear helper
@@ -790,7 +1627,7 @@ impl DatabaseHelper {
 
 #[cfg(test)]
 mod tests {
-    use nostr::{FromBech32, JsonUtil, Keys, SecretKey};
+    use nostr::{EventBuilder, FromBech32, JsonUtil, Keys, Metadata, SecretKey, Tag};
 
     use super::*;
 
@@ -1017,4 +1854,138 @@ mod tests {
             vec![ev]
         );
     }
+
+    #[tokio::test]
+    async fn test_memory_estimate() {
+        let indexes = DatabaseHelper::unbounded();
+
+        let mut last: usize = indexes.memory_estimate().await;
+        assert_eq!(last, 0);
+
+        let keys = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+        for i in 0..10 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .tag(Tag::public_key(keys.public_key))
+                .sign_with_keys(&keys)
+                .unwrap();
+            indexes.index_event(&event).await;
+
+            let estimate: usize = indexes.memory_estimate().await;
+            assert!(estimate >= last);
+            last = estimate;
+        }
+
+        assert!(last > 0);
+    }
+
+    #[tokio::test]
+    async fn test_delete_tombstone() {
+        let helper = DatabaseHelper::unbounded();
+        let keys = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+
+        let deleted = EventBuilder::text_note("Deleted")
+            .sign_with_keys(&keys)
+            .unwrap();
+        let tombstoned = EventBuilder::text_note("Tombstoned")
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        assert!(helper.index_event(&deleted).await.status.is_success());
+        assert!(helper.index_event(&tombstoned).await.status.is_success());
+
+        // Plain delete doesn't mark as deleted
+        let ids = helper.delete(Filter::new().id(deleted.id)).await.unwrap();
+        assert!(ids.contains(&deleted.id));
+        assert!(!helper.has_event_id_been_deleted(&deleted.id).await);
+        assert!(helper.index_event(&deleted).await.status.is_success());
+
+        assert_eq!(
+            helper
+                .delete_with_tombstone(Filter::new().id(tombstoned.id))
+                .await,
+            1
+        );
+        assert!(helper.has_event_id_been_deleted(&tombstoned.id).await);
+        assert_eq!(
+            helper.index_event(&tombstoned).await.status,
+            SaveEventStatus::Rejected(RejectedReason::Deleted)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restore() {
+        let helper = DatabaseHelper::unbounded();
+        let keys = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+
+        let metadata = |name: &str, secs: u64| {
+            EventBuilder::metadata(&Metadata::new().name(name))
+                .custom_created_at(Timestamp::from_secs(secs))
+                .sign_with_keys(&keys)
+                .unwrap()
+        };
+
+        let old = metadata("old", 1000);
+        let new = metadata("new", 2000);
+        assert!(helper.index_event(&old).await.status.is_success());
+
+        let res = helper.index_event(&new).await;
+        assert!(res.to_discard.contains(&old.id));
+
+        // Roll back the save of the new event
+        helper.wipe_filter(Filter::new().id(new.id)).await;
+        helper.restore([old.clone()]).await;
+
+        assert!(helper.has_event(&old.id).await);
+        assert!(!helper.has_event(&new.id).await);
+        assert!(!helper.has_event_id_been_deleted(&old.id).await);
+        assert!(helper.index_event(&new).await.status.is_success());
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut helper = InternalDatabaseHelper::default();
+        let keys = Keys::new(SecretKey::from_bech32(SECRET_KEY_A).unwrap());
+
+        let metadata = |name: &str, secs: u64| {
+            EventBuilder::metadata(&Metadata::new().name(name))
+                .custom_created_at(Timestamp::from_secs(secs))
+                .sign_with_keys(&keys)
+                .unwrap()
+        };
+
+        let live = metadata("live", 3000);
+        assert!(helper.index_event(&live).status.is_success());
+
+        // Nothing to prune
+        assert_eq!(helper.prune(), PruneReport::default());
+
+        // Inject dead entries, bypassing the checks
+        let note = EventBuilder::text_note("Deleted")
+            .sign_with_keys(&keys)
+            .unwrap();
+        helper.deleted_ids.insert(note.id, None);
+        for event in [metadata("first", 1000), metadata("second", 2000), note] {
+            let event: DatabaseEvent = Arc::new(event);
+            helper.events.insert(event.clone());
+            helper.ids.insert(event.id, event.clone());
+            helper
+                .kind_author_index
+                .entry((event.kind, event.pubkey))
+                .or_default()
+                .insert(event);
+        }
+        assert_eq!(helper.events.len(), 4);
+
+        assert_eq!(
+            helper.prune(),
+            PruneReport {
+                replaced: 2,
+                deleted: 1
+            }
+        );
+        assert_eq!(
+            helper.query(Filter::new()).cloned().collect::<Vec<_>>(),
+            vec![live]
+        );
+    }
 }
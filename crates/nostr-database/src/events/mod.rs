@@ -4,6 +4,7 @@
 
 use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt;
+use std::future::Future;
 use std::sync::Arc;
 
 use nostr::prelude::*;
@@ -15,6 +16,96 @@ use crate::{DatabaseError, Events, Profile};
 /// NIP65 relays map
 pub type RelaysMap = HashMap<RelayUrl, Option<RelayMetadata>>;
 
+/// Check if the [`Event`] matches the [`Filter`]
+///
+/// This is the matching logic used by the in-memory backend, exposed for custom backends:
+///
+/// * `ids`, `authors` and `kinds` match if not set, empty or containing the event value;
+/// * `since` and `until` are inclusive bounds of the event `created_at`;
+/// * every tag constraint (i.e., `#p`) must match at least one of its values;
+/// * `limit` is ignored, since it applies to the whole query and not to the single event;
+/// * `search` is an ASCII case-insensitive substring match of the event content.
+///   Backends supporting full-text search may apply richer semantics in their queries.
+///
+/// Deletion and expiration (NIP-40) aren't checked.
+#[inline]
+pub fn match_event(filter: &Filter, event: &Event) -> bool {
+    filter.match_event(event)
+}
+
+/// Cap the [`Filter`] `limit` to `max`, if the filter doesn't already set a smaller one
+///
+/// Used by the backends to apply their max number of query results. `None` means no limits.
+pub fn cap_filter_limit(filter: Filter, max: Option<usize>) -> Filter {
+    match (filter.limit, max) {
+        (Some(limit), Some(max)) if limit <= max => filter,
+        (_, Some(max)) => filter.limit(max),
+        (_, None) => filter,
+    }
+}
+
+/// Query a page of events, newest first, with the `query` function
+///
+/// This is the [`NostrEventsDatabase::query_page`] logic, exposed for the backends that override it:
+/// the `query` function must not cap the number of returned events below the filter `limit`,
+/// otherwise a short result is taken as the end of the events.
+/// Backends with a max number of query results should cap the page `limit` instead.
+pub async fn query_page_with<F, Fut>(
+    query: F,
+    filter: Filter,
+    cursor: Option<PageCursor>,
+    limit: usize,
+) -> Result<(Events, Option<PageCursor>), DatabaseError>
+where
+    F: Fn(Filter) -> Fut,
+    Fut: Future<Output = Result<Events, DatabaseError>>,
+{
+    if limit == 0 {
+        return Ok((Events::new(&filter.limit(0)), cursor));
+    }
+
+    let mut filter: Filter = filter;
+
+    if let Some(cursor) = &cursor {
+        filter.until = match filter.until {
+            Some(until) => Some(until.min(cursor.created_at)),
+            None => Some(cursor.created_at),
+        };
+    }
+
+    // Events sharing the cursor timestamp may have already been returned,
+    // so fetch more until the page is full or the events are exhausted.
+    let mut fetch: usize = limit.saturating_add(1);
+    let events: Vec<Event> = loop {
+        let events: Events = query(filter.clone().limit(fetch)).await?;
+        let exhausted: bool = events.len() < fetch;
+        let events: Vec<Event> = events
+            .into_iter()
+            .filter(|e| match &cursor {
+                Some(cursor) => cursor.precedes(e),
+                None => true,
+            })
+            .collect();
+
+        if exhausted || events.len() > limit {
+            break events;
+        }
+
+        fetch = fetch.saturating_mul(2);
+    };
+
+    let next: Option<PageCursor> = if events.len() > limit {
+        events.get(limit - 1).map(PageCursor::from_event)
+    } else {
+        None
+    };
+
+    let mut page: Events = Events::new(&filter.limit(limit));
+    page.extend(events.into_iter().take(limit));
+
+    Ok((page, next))
+}
+
 /// Database event status
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum DatabaseEventStatus {
@@ -41,6 +132,8 @@ pub enum RejectedReason {
     Replaced,
     /// Attempt to delete a non-owned event
     InvalidDelete,
+    /// The event ID or signature is invalid
+    InvalidSignature,
     /// Other reason
     Other,
 }
@@ -62,6 +155,43 @@ impl SaveEventStatus {
     }
 }
 
+/// Report of a [`NostrEventsDatabase::prune`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    /// Events superseded by a newer replaceable or addressable version
+    pub replaced: usize,
+    /// Events targeted by a stored deletion (NIP-09)
+    pub deleted: usize,
+}
+
+/// Cursor for paginating the query results
+///
+/// Opaque position of the last event returned by [`NostrEventsDatabase::query_page`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PageCursor {
+    created_at: Timestamp,
+    id: EventId,
+}
+
+impl PageCursor {
+    #[inline]
+    fn from_event(event: &Event) -> Self {
+        Self {
+            created_at: event.created_at,
+            id: event.id,
+        }
+    }
+
+    /// Check if the event comes after the cursor (newest first, lowest ID first on ties)
+    ///
+    /// Lookup ID: EVENT_ORD_IMPL
+    #[inline]
+    fn precedes(&self, event: &Event) -> bool {
+        event.created_at < self.created_at
+            || (event.created_at == self.created_at && event.id > self.id)
+    }
+}
+
 #[doc(hidden)]
 pub trait IntoNostrEventsDatabase {
     fn into_database(self) -> Arc<dyn NostrEventsDatabase>;
@@ -103,6 +233,45 @@ pub trait NostrEventsDatabase: fmt::Debug + Send + Sync {
         event: &'a Event,
     ) -> BoxedFuture<'a, Result<SaveEventStatus, DatabaseError>>;
 
+    /// Save [`Event`] into store only if newer than the stored one
+    ///
+    /// For replaceable and addressable events, return [`RejectedReason::Replaced`]
+    /// if the stored event for the same author, kind (and identifier) is newer or as old.
+    /// Other events are saved as with [`NostrEventsDatabase::save_event`].
+    ///
+    /// The default implementation isn't atomic: backends should override it
+    /// to check and store the event in a single write.
+    ///
+    /// **This method assumes that [`Event`] was already verified**
+    fn replace_if_newer<'a>(
+        &'a self,
+        event: &'a Event,
+    ) -> BoxedFuture<'a, Result<SaveEventStatus, DatabaseError>> {
+        Box::pin(async move {
+            let filter: Filter = if event.kind.is_replaceable() {
+                Filter::new().author(event.pubkey).kind(event.kind)
+            } else if event.kind.is_addressable() {
+                match event.tags.identifier() {
+                    Some(identifier) => Filter::new()
+                        .author(event.pubkey)
+                        .kind(event.kind)
+                        .identifier(identifier),
+                    None => return self.save_event(event).await,
+                }
+            } else {
+                return self.save_event(event).await;
+            };
+
+            if let Some((_, created_at)) = self.latest(filter).await? {
+                if created_at >= event.created_at {
+                    return Ok(SaveEventStatus::Rejected(RejectedReason::Replaced));
+                }
+            }
+
+            self.save_event(event).await
+        })
+    }
+
     /// Check event status by ID
     ///
     /// Check if the event is saved, deleted or not existent.
@@ -111,6 +280,20 @@ pub trait NostrEventsDatabase: fmt::Debug + Send + Sync {
         event_id: &'a EventId,
     ) -> BoxedFuture<'a, Result<DatabaseEventStatus, DatabaseError>>;
 
+    /// Check if the event is saved
+    ///
+    /// Return `false` if the event has been deleted or doesn't exist.
+    /// Use [`NostrEventsDatabase::check_id`] to distinguish between the two cases.
+    fn has_event<'a>(
+        &'a self,
+        event_id: &'a EventId,
+    ) -> BoxedFuture<'a, Result<bool, DatabaseError>> {
+        Box::pin(async move {
+            let status: DatabaseEventStatus = self.check_id(event_id).await?;
+            Ok(matches!(status, DatabaseEventStatus::Saved))
+        })
+    }
+
     // TODO: rename to `check_coordinate`?
     /// Check if [`Coordinate`] has been deleted before a certain [`Timestamp`]
     fn has_coordinate_been_deleted<'a>(
@@ -128,11 +311,75 @@ pub trait NostrEventsDatabase: fmt::Debug + Send + Sync {
     /// Count the number of events found with [`Filter`].
     ///
     /// Use `Filter::new()` or `Filter::default()` to count all events.
+    ///
+    /// If the filter has a `limit`, the count is capped at it (like the relay `COUNT`).
     fn count(&self, filter: Filter) -> BoxedFuture<Result<usize, DatabaseError>>;
 
+    /// Count the number of events matching at least one of the [`Filter`]s.
+    ///
+    /// Events matching more than one filter are counted only once.
+    fn count_by_filters(&self, filters: Vec<Filter>) -> BoxedFuture<Result<usize, DatabaseError>> {
+        Box::pin(async move {
+            let mut ids: HashSet<EventId> = HashSet::new();
+
+            for filter in filters.into_iter() {
+                let events: Events = self.query(filter).await?;
+                ids.extend(events.into_iter().map(|e| e.id));
+            }
+
+            Ok(ids.len())
+        })
+    }
+
     /// Query stored events.
     fn query(&self, filter: Filter) -> BoxedFuture<Result<Events, DatabaseError>>;
 
+    /// Query a page of stored events, newest first.
+    ///
+    /// Return up to `limit` events after the [`PageCursor`] (from the newest if `None`)
+    /// and the cursor of the following page, or `None` if there are no more events.
+    ///
+    /// The `limit` of the [`Filter`] is ignored.
+    /// A zero `limit` returns no events and the same cursor.
+    fn query_page(
+        &self,
+        filter: Filter,
+        cursor: Option<PageCursor>,
+        limit: usize,
+    ) -> BoxedFuture<Result<(Events, Option<PageCursor>), DatabaseError>> {
+        Box::pin(query_page_with(
+            |filter| self.query(filter),
+            filter,
+            cursor,
+            limit,
+        ))
+    }
+
+    /// Get the IDs of the events matching the [`Filter`].
+    ///
+    /// Match the same events of [`NostrEventsDatabase::query`] (`limit` included),
+    /// without cloning or decoding them where the backend allows it.
+    fn event_ids(&self, filter: Filter) -> BoxedFuture<Result<BTreeSet<EventId>, DatabaseError>> {
+        Box::pin(async move {
+            let events: Events = self.query(filter).await?;
+            Ok(events.into_iter().map(|e| e.id).collect())
+        })
+    }
+
+    /// Get the [`EventId`] and [`Timestamp`] of the newest event matching the [`Filter`].
+    ///
+    /// In case of identical timestamps, the event with the lowest ID is returned (NIP-01).
+    fn latest(
+        &self,
+        filter: Filter,
+    ) -> BoxedFuture<Result<Option<(EventId, Timestamp)>, DatabaseError>> {
+        Box::pin(async move {
+            // Lookup ID: EVENT_ORD_IMPL
+            let events: Events = self.query(filter.limit(1)).await?;
+            Ok(events.first().map(|e| (e.id, e.created_at)))
+        })
+    }
+
     /// Get `negentropy` items
     fn negentropy_items(
         &self,
@@ -145,7 +392,18 @@ pub trait NostrEventsDatabase: fmt::Debug + Send + Sync {
     }
 
     /// Delete all events that match the [Filter]
-    fn delete(&self, filter: Filter) -> BoxedFuture<Result<(), DatabaseError>>;
+    ///
+    /// Return the number of deleted events.
+    fn delete(&self, filter: Filter) -> BoxedFuture<Result<usize, DatabaseError>>;
+
+    /// Remove the stored events that are superseded by a newer replaceable or addressable version,
+    /// or targeted by a stored deletion (NIP-09)
+    ///
+    /// The default implementation does nothing,
+    /// for the backends that already remove these events when saving.
+    fn prune(&self) -> BoxedFuture<Result<PruneReport, DatabaseError>> {
+        Box::pin(async move { Ok(PruneReport::default()) })
+    }
 }
 
 /// Nostr Event Store Extension
@@ -276,3 +534,78 @@ pub trait NostrEventsDatabaseExt: NostrEventsDatabase {
 }
 
 impl<T: NostrEventsDatabase + ?Sized> NostrEventsDatabaseExt for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_event() {
+        let keys = Keys::generate();
+        let other = Keys::generate();
+        let created_at = Timestamp::from_secs(1_700_000_000);
+
+        let event = EventBuilder::text_note("Hello Nostr")
+            .tag(Tag::public_key(other.public_key))
+            .tag(Tag::hashtag("rust"))
+            .custom_created_at(created_at)
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        let cases: Vec<(&str, Filter, bool)> = vec![
+            ("empty", Filter::new(), true),
+            ("id", Filter::new().id(event.id), true),
+            ("other id", Filter::new().id(EventId::all_zeros()), false),
+            ("author", Filter::new().author(keys.public_key), true),
+            (
+                "other author",
+                Filter::new().author(other.public_key),
+                false,
+            ),
+            ("kind", Filter::new().kind(Kind::TextNote), true),
+            ("other kind", Filter::new().kind(Kind::Metadata), false),
+            ("since equal", Filter::new().since(created_at), true),
+            ("since after", Filter::new().since(created_at + 1), false),
+            ("until equal", Filter::new().until(created_at), true),
+            ("until before", Filter::new().until(created_at - 1), false),
+            ("p tag", Filter::new().pubkey(other.public_key), true),
+            ("other p tag", Filter::new().pubkey(keys.public_key), false),
+            ("t tag", Filter::new().hashtags(["nostr", "rust"]), true),
+            ("other t tag", Filter::new().hashtag("nostr"), false),
+            (
+                "p and t tags",
+                Filter::new().pubkey(other.public_key).hashtag("rust"),
+                true,
+            ),
+            (
+                "p and other t tags",
+                Filter::new().pubkey(other.public_key).hashtag("nostr"),
+                false,
+            ),
+            ("limit ignored", Filter::new().limit(0), true),
+            ("search", Filter::new().search("hello"), true),
+            (
+                "search case-insensitive",
+                Filter::new().search("NOSTR"),
+                true,
+            ),
+            ("other search", Filter::new().search("bitcoin"), false),
+            (
+                "all dimensions",
+                Filter::new()
+                    .id(event.id)
+                    .author(keys.public_key)
+                    .kind(Kind::TextNote)
+                    .since(created_at)
+                    .until(created_at)
+                    .pubkey(other.public_key)
+                    .search("nostr"),
+                true,
+            ),
+        ];
+
+        for (name, filter, expected) in cases.into_iter() {
+            assert_eq!(match_event(&filter, &event), expected, "{name}");
+        }
+    }
+}
@@ -12,6 +12,7 @@
 use std::sync::Arc;
 
 pub use nostr;
+use nostr::util::BoxedFuture;
 
 mod collections;
 mod error;
@@ -21,18 +22,24 @@ pub mod flatbuffers;
 pub mod memory;
 pub mod prelude;
 pub mod profile;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_suite;
 mod wipe;
 
 pub use self::collections::events::Events;
 pub use self::error::DatabaseError;
-pub use self::events::helper::{DatabaseEventResult, DatabaseHelper};
+pub use self::events::helper::{DatabaseEventResult, DatabaseHelper, TieBreaker};
 pub use self::events::{
-    DatabaseEventStatus, IntoNostrEventsDatabase, NostrEventsDatabase, NostrEventsDatabaseExt,
-    RejectedReason, SaveEventStatus,
+    cap_filter_limit, match_event, query_page_with, DatabaseEventStatus, IntoNostrEventsDatabase,
+    NostrEventsDatabase, NostrEventsDatabaseExt, PageCursor, PruneReport, RejectedReason,
+    SaveEventStatus,
 };
 #[cfg(feature = "flatbuf")]
 pub use self::flatbuffers::{FlatBufferBuilder, FlatBufferDecode, FlatBufferEncode};
-pub use self::memory::{MemoryDatabase, MemoryDatabaseOptions};
+pub use self::memory::{
+    ImportReport, IndexedTags, MemoryDatabase, MemoryDatabaseOptions, Order, QueryOptions,
+    SaveEventOutcome, SearchOptions, SearchRank,
+};
 pub use self::profile::Profile;
 pub use self::wipe::NostrDatabaseWipe;
 
@@ -56,12 +63,32 @@ pub enum Backend {
 impl Backend {
     /// Check if it's a persistent backend
     ///
-    /// All values different from [`Backend::Memory`] are considered persistent
+    /// All values different from [`Backend::Memory`] are considered persistent.
+    /// Note that [`Backend::IndexedDB`] data may still be cleared by the browser
+    /// (i.e., in private browsing mode, at the end of the session).
     pub fn is_persistent(&self) -> bool {
         !matches!(self, Self::Memory)
     }
 }
 
+/// Kinds and NIPs special-cased by the database backend
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct HandledKinds {
+    /// Replaceable events (kinds `0`, `3` and `10000-19999`) are replaced by the newer ones
+    pub replaceable: bool,
+    /// Addressable events (kinds `30000-39999`) are replaced by the newer ones
+    /// with the same identifier
+    pub addressable: bool,
+    /// Ephemeral events (kinds `20000-29999`) aren't stored
+    pub ephemeral: bool,
+    /// Deletion requests (NIP-09) are enforced
+    pub nip09: bool,
+    /// Expired events (NIP-40) are rejected and excluded from the queries
+    pub nip40: bool,
+    /// Requests to vanish (NIP-62) are enforced
+    pub nip62: bool,
+}
+
 #[doc(hidden)]
 pub trait IntoNostrDatabase {
     fn into_nostr_database(self) -> Arc<dyn NostrDatabase>;
@@ -95,6 +122,21 @@ where
 pub trait NostrDatabase: NostrEventsDatabase + NostrDatabaseWipe {
     /// Name of the backend database used
     fn backend(&self) -> Backend;
+
+    /// Kinds and NIPs special-cased by the backend
+    ///
+    /// The default implementation reports none of them.
+    fn handled_kinds(&self) -> HandledKinds {
+        HandledKinds::default()
+    }
+
+    /// Flush the pending writes to the storage
+    ///
+    /// Once this returns, all the events saved before the call are durable and visible to queries.
+    /// The default implementation does nothing, for backends that write synchronously.
+    fn flush(&self) -> BoxedFuture<Result<(), DatabaseError>> {
+        Box::pin(async move { Ok(()) })
+    }
 }
 
 #[cfg(test)]
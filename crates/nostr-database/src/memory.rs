@@ -4,17 +4,81 @@
 
 //! Memory (RAM) Storage backend for Nostr apps
 
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::num::NonZeroUsize;
 use std::sync::Arc;
 
 use nostr::prelude::*;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
 use tokio::sync::RwLock;
 
 use crate::{
-    Backend, DatabaseError, DatabaseEventResult, DatabaseEventStatus, DatabaseHelper, Events,
-    NostrDatabase, NostrDatabaseWipe, NostrEventsDatabase, RejectedReason, SaveEventStatus,
+    cap_filter_limit, query_page_with, Backend, DatabaseError, DatabaseEventResult,
+    DatabaseEventStatus, DatabaseHelper, Events, HandledKinds, NostrDatabase, NostrDatabaseWipe,
+    NostrEventsDatabase, PageCursor, PruneReport, RejectedReason, SaveEventStatus, TieBreaker,
 };
 
+/// Set of single-letter tags
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IndexedTags(u64);
+
+impl IndexedTags {
+    /// Empty set
+    #[inline]
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    /// Lowercase letters first, then the uppercase ones
+    #[inline]
+    fn bit(tag: &SingleLetterTag) -> u64 {
+        let offset: u8 = if tag.uppercase { 26 } else { 0 };
+        1 << (tag.character as u8 + offset)
+    }
+
+    /// Add a tag to the set
+    #[inline]
+    pub fn insert(&mut self, tag: SingleLetterTag) {
+        self.0 |= Self::bit(&tag);
+    }
+
+    /// Check if the set contains the tag
+    #[inline]
+    pub fn contains(&self, tag: &SingleLetterTag) -> bool {
+        self.0 & Self::bit(tag) != 0
+    }
+
+    /// Check if the set is empty
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Iterate the tags of the set
+    pub fn iter(&self) -> impl Iterator<Item = SingleLetterTag> + '_ {
+        (0..52u8)
+            .filter(|i| self.0 & (1 << i) != 0)
+            .filter_map(|i| {
+                let c: u8 = if i < 26 { b'a' + i } else { b'A' + i - 26 };
+                SingleLetterTag::from_char(c as char).ok()
+            })
+    }
+}
+
+impl FromIterator<SingleLetterTag> for IndexedTags {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = SingleLetterTag>,
+    {
+        let mut set: Self = Self::new();
+        for tag in iter.into_iter() {
+            set.insert(tag);
+        }
+        set
+    }
+}
+
 /// Database options
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct MemoryDatabaseOptions {
@@ -24,6 +88,27 @@ pub struct MemoryDatabaseOptions {
     ///
     /// `None` means no limits.
     pub max_events: Option<usize>,
+    /// Max number of events returned by a single query (default: None)
+    ///
+    /// Applied when the filter doesn't set a smaller `limit`. `None` means no limits.
+    pub max_query_results: Option<usize>,
+    /// Single-letter tags to index (default: empty)
+    ///
+    /// Speeds up queries constraining these tags (i.e., `#p`), at the cost of more memory.
+    pub index_tags: IndexedTags,
+    /// Conflict resolution for replaceable/addressable events with identical timestamps
+    /// (default: [`TieBreaker::LowestId`])
+    pub tie_breaker: TieBreaker,
+    /// Max number of deleted event IDs to remember (default: None)
+    ///
+    /// When the cap is reached, the oldest deletion records are forgotten,
+    /// so a forgotten event can be saved again. `None` means no limits.
+    pub max_deletion_records: Option<NonZeroUsize>,
+    /// Verify the event ID and signature before saving it (default: false)
+    ///
+    /// The events are expected to be already verified, so this is disabled by default.
+    /// Enable it when saving events from untrusted sources.
+    pub verify_on_save: bool,
 }
 
 impl Default for MemoryDatabaseOptions {
@@ -31,6 +116,11 @@ impl Default for MemoryDatabaseOptions {
         Self {
             events: false,
             max_events: Some(35_000),
+            max_query_results: None,
+            index_tags: IndexedTags::new(),
+            tie_breaker: TieBreaker::default(),
+            max_deletion_records: None,
+            verify_on_save: false,
         }
     }
 }
@@ -42,7 +132,100 @@ impl MemoryDatabaseOptions {
     }
 }
 
+/// Query order
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Order {
+    /// Ascending (oldest first)
+    Asc,
+    /// Descending (newest first)
+    #[default]
+    Desc,
+}
+
+/// Query options
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct QueryOptions {
+    /// Order of the events (default: descending)
+    pub order: Order,
+}
+
+impl QueryOptions {
+    /// New default query options
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set order
+    #[inline]
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+}
+
+/// Ranking of the full-text search results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SearchRank {
+    /// Newest first
+    #[default]
+    TimeDesc,
+    /// Most relevant first
+    ///
+    /// Events with more occurrences of the search query come first,
+    /// then the ones where it occurs earlier in the content, then the newest.
+    Relevance,
+}
+
+/// Search options
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SearchOptions {
+    /// Ranking of the results (default: newest first)
+    pub rank: SearchRank,
+}
+
+impl SearchOptions {
+    /// New default search options
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set ranking
+    #[inline]
+    pub fn rank(mut self, rank: SearchRank) -> Self {
+        self.rank = rank;
+        self
+    }
+}
+
+/// Report of a [`MemoryDatabase::import_jsonl`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Saved events
+    pub saved: usize,
+    /// Duplicated events
+    pub duplicate: usize,
+    /// Events rejected for other reasons (i.e., invalid signature, replaced, deleted)
+    pub rejected: usize,
+    /// Lines that can't be deserialized as [`Event`]
+    pub invalid_json: usize,
+}
+
+/// Outcome of a [`MemoryDatabase::save_event_full`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveEventOutcome {
+    /// Status
+    pub status: SaveEventStatus,
+    /// IDs of the events superseded by the saved one (replaceable and addressable events)
+    ///
+    /// May also include the events evicted to respect [`MemoryDatabaseOptions::max_events`].
+    pub replaced: Vec<EventId>,
+}
+
 /// Memory Database (RAM)
+///
+/// Cheap to clone: the clones share the same store.
 #[derive(Debug, Clone)]
 pub struct MemoryDatabase {
     opts: MemoryDatabaseOptions,
@@ -65,20 +248,281 @@ impl MemoryDatabase {
     /// New Memory database
     pub fn with_opts(opts: MemoryDatabaseOptions) -> Self {
         Self {
-            opts,
             seen_event_ids: Arc::new(RwLock::new(SeenTracker::new(opts.max_events))),
-            helper: match opts.max_events {
-                Some(max) => DatabaseHelper::bounded(max),
-                None => DatabaseHelper::unbounded(),
-            },
+            helper: DatabaseHelper::custom(
+                opts.max_events,
+                opts.index_tags.iter(),
+                opts.tie_breaker,
+                opts.max_deletion_records,
+            ),
+            opts,
         }
     }
 }
 
+impl MemoryDatabase {
+    /// Query stored events with [`QueryOptions`]
+    ///
+    /// The `limit` of the [`Filter`] is applied in the requested order:
+    /// with [`Order::Asc`], the oldest events are returned.
+    pub fn query_with_options(
+        &self,
+        filter: Filter,
+        opts: QueryOptions,
+    ) -> BoxedFuture<Result<Vec<Event>, DatabaseError>> {
+        Box::pin(async move {
+            let filter: Filter = cap_filter_limit(filter, self.opts.max_query_results);
+            Ok(match opts.order {
+                Order::Asc => self.helper.query_asc(filter).await,
+                Order::Desc => self.helper.query(filter).await.to_vec(),
+            })
+        })
+    }
+
+    /// Query stored events, keeping only the ones whose author matches the predicate
+    ///
+    /// The predicate is applied before cloning the events (i.e., to skip the muted authors),
+    /// and the `limit` of the [`Filter`] after it.
+    pub fn query_filtered<'a, F>(
+        &'a self,
+        filter: Filter,
+        keep: F,
+    ) -> BoxedFuture<'a, Result<Events, DatabaseError>>
+    where
+        F: Fn(&PublicKey) -> bool + Send + 'a,
+    {
+        Box::pin(async move {
+            let filter: Filter = cap_filter_limit(filter, self.opts.max_query_results);
+            Ok(self.helper.query_filtered(filter, keep).await)
+        })
+    }
+
+    /// Query stored events, ranking the full-text search results with [`SearchOptions`]
+    ///
+    /// The `limit` of the [`Filter`] is applied after the ranking.
+    /// Without a `search` query, the events are returned newest first.
+    pub fn query_search(
+        &self,
+        filter: Filter,
+        opts: SearchOptions,
+    ) -> BoxedFuture<Result<Vec<Event>, DatabaseError>> {
+        Box::pin(async move {
+            let filter: Filter = cap_filter_limit(filter, self.opts.max_query_results);
+
+            Ok(match (opts.rank, filter.search.clone()) {
+                (SearchRank::Relevance, Some(query)) => {
+                    let limit: Option<usize> = filter.limit;
+
+                    // Lookup ID: EVENT_ORD_IMPL
+                    let mut events: Vec<Event> =
+                        self.helper.query(filter.remove_limit()).await.to_vec();
+
+                    // Stable sort: equally relevant events stay newest first
+                    events.sort_by_cached_key(|event| {
+                        let (occurrences, position) = search_relevance(&event.content, &query);
+                        (Reverse(occurrences), position)
+                    });
+
+                    if let Some(limit) = limit {
+                        events.truncate(limit);
+                    }
+
+                    events
+                }
+                _ => self.helper.query(filter).await.to_vec(),
+            })
+        })
+    }
+
+    /// Save [`Event`] into store, returning the IDs of the events that it replaced
+    pub fn save_event_full<'a>(
+        &'a self,
+        event: &'a Event,
+    ) -> BoxedFuture<'a, Result<SaveEventOutcome, DatabaseError>> {
+        Box::pin(async move {
+            if self.opts.verify_on_save && event.verify().is_err() {
+                return Ok(SaveEventOutcome {
+                    status: SaveEventStatus::Rejected(RejectedReason::InvalidSignature),
+                    replaced: Vec::new(),
+                });
+            }
+
+            // Events are indexed immediately under the helper write lock (no batching),
+            // so they are applied in call order.
+            if self.opts.events {
+                let DatabaseEventResult { status, to_discard } =
+                    self.helper.index_event(event).await;
+
+                // On success, the discarded events of a replaceable or addressable kind
+                // are the ones superseded by the new event
+                let replaced: Vec<EventId> = match status {
+                    SaveEventStatus::Success
+                        if event.kind.is_replaceable() || event.kind.is_addressable() =>
+                    {
+                        to_discard
+                            .into_iter()
+                            .filter(|id| id != &event.id)
+                            .collect()
+                    }
+                    _ => Vec::new(),
+                };
+
+                Ok(SaveEventOutcome { status, replaced })
+            } else {
+                // Mark it as seen
+                let mut seen_event_ids = self.seen_event_ids.write().await;
+                seen_event_ids.seen(event.id, None);
+
+                Ok(SaveEventOutcome {
+                    status: SaveEventStatus::Rejected(RejectedReason::Other),
+                    replaced: Vec::new(),
+                })
+            }
+        })
+    }
+
+    /// Get the events by IDs, acquiring the lock once
+    ///
+    /// The results are positional: `None` for the missing or deleted events.
+    pub fn event_by_ids<'a>(
+        &'a self,
+        ids: &'a [EventId],
+    ) -> BoxedFuture<'a, Result<Vec<Option<Event>>, DatabaseError>> {
+        Box::pin(async move {
+            if self.opts.events {
+                Ok(self.helper.event_by_ids(ids).await)
+            } else {
+                Ok(vec![None; ids.len()])
+            }
+        })
+    }
+
+    /// Approximate count of the events matching the [`Filter`]
+    ///
+    /// Faster than the exact [`NostrEventsDatabase::count`] on large datasets
+    /// for the author-only and kind-only filters.
+    /// The estimate is never lower than the exact count and exceeds it by at most
+    /// `error_rate` times the number of stored events, with probability at least 98%.
+    /// See [`DatabaseHelper::count_approx`] for the filters that are counted exactly.
+    pub fn count_approx(
+        &self,
+        filter: Filter,
+        error_rate: f64,
+    ) -> BoxedFuture<Result<usize, DatabaseError>> {
+        Box::pin(async move { Ok(self.helper.count_approx(filter, error_rate).await) })
+    }
+
+    /// Delete the events matching the [`Filter`], marking them as deleted
+    ///
+    /// Unlike [`NostrEventsDatabase::delete`], the IDs are remembered as deleted (as with NIP-09),
+    /// so the subsequent saves of the events are rejected with [`RejectedReason::Deleted`].
+    ///
+    /// Return the number of deleted events.
+    pub async fn delete_with_tombstone(&self, filter: Filter) -> usize {
+        self.helper.delete_with_tombstone(filter).await
+    }
+
+    /// Wipe the events matching the [`Filter`]
+    ///
+    /// Unlike [`NostrEventsDatabase::delete`], the removed events aren't marked as deleted,
+    /// and the deletion bookkeeping of the subset is cleared, so they can be saved again
+    /// as in a fresh database. In particular, it's forgotten:
+    ///
+    /// * the IDs and coordinates targeted by the removed deletion requests (NIP-09);
+    /// * the IDs set in the [`Filter`];
+    /// * the deleted coordinates matching the [`Filter`] `authors` and `kinds`.
+    ///
+    /// Return the number of removed events.
+    pub async fn wipe_filter(&self, filter: Filter) -> usize {
+        self.helper.wipe_filter(filter).await
+    }
+
+    /// Import events from a line-delimited JSON dump (i.e., `strfry export`)
+    ///
+    /// Every line is deserialized, verified and saved as an [`Event`].
+    /// Malformed lines are counted and skipped.
+    pub fn import_jsonl<'a, R>(
+        &'a self,
+        reader: R,
+    ) -> BoxedFuture<'a, Result<ImportReport, DatabaseError>>
+    where
+        R: AsyncBufRead + Unpin + Send + 'a,
+    {
+        Box::pin(async move {
+            let mut report: ImportReport = ImportReport::default();
+            let mut lines = reader.lines();
+
+            while let Some(line) = lines.next_line().await.map_err(DatabaseError::backend)? {
+                let line: &str = line.trim();
+
+                if line.is_empty() {
+                    continue;
+                }
+
+                let event: Event = match Event::from_json(line) {
+                    Ok(event) => event,
+                    Err(..) => {
+                        report.invalid_json += 1;
+                        continue;
+                    }
+                };
+
+                if event.verify().is_err() {
+                    report.rejected += 1;
+                    continue;
+                }
+
+                match self.save_event(&event).await? {
+                    SaveEventStatus::Success => report.saved += 1,
+                    SaveEventStatus::Rejected(RejectedReason::Duplicate) => report.duplicate += 1,
+                    SaveEventStatus::Rejected(..) => report.rejected += 1,
+                }
+            }
+
+            Ok(report)
+        })
+    }
+}
+
+/// Count the ASCII case-insensitive occurrences of the query and find the first one
+fn search_relevance(content: &str, query: &str) -> (usize, usize) {
+    let mut occurrences: usize = 0;
+    let mut first: usize = usize::MAX;
+
+    if query.is_empty() {
+        return (occurrences, first);
+    }
+
+    for (index, window) in content.as_bytes().windows(query.len()).enumerate() {
+        if window.eq_ignore_ascii_case(query.as_bytes()) {
+            occurrences += 1;
+            first = first.min(index);
+        }
+    }
+
+    (occurrences, first)
+}
+
 impl NostrDatabase for MemoryDatabase {
     fn backend(&self) -> Backend {
         Backend::Memory
     }
+
+    fn handled_kinds(&self) -> HandledKinds {
+        if !self.opts.events {
+            return HandledKinds::default();
+        }
+
+        // The expired events are rejected but, once saved, they aren't excluded from the queries
+        HandledKinds {
+            replaceable: true,
+            addressable: true,
+            ephemeral: true,
+            nip09: true,
+            nip40: false,
+            nip62: false,
+        }
+    }
 }
 
 impl NostrEventsDatabase for MemoryDatabase {
@@ -87,16 +531,27 @@ impl NostrEventsDatabase for MemoryDatabase {
         event: &'a Event,
     ) -> BoxedFuture<'a, Result<SaveEventStatus, DatabaseError>> {
         Box::pin(async move {
-            if self.opts.events {
-                let DatabaseEventResult { status, .. } = self.helper.index_event(event).await;
-                Ok(status)
-            } else {
-                // Mark it as seen
-                let mut seen_event_ids = self.seen_event_ids.write().await;
-                seen_event_ids.seen(event.id, None);
+            let SaveEventOutcome { status, .. } = self.save_event_full(event).await?;
+            Ok(status)
+        })
+    }
+
+    fn replace_if_newer<'a>(
+        &'a self,
+        event: &'a Event,
+    ) -> BoxedFuture<'a, Result<SaveEventStatus, DatabaseError>> {
+        Box::pin(async move {
+            // Events aren't stored: only mark it as seen
+            if !self.opts.events {
+                return self.save_event(event).await;
+            }
 
-                Ok(SaveEventStatus::Rejected(RejectedReason::Other))
+            if self.opts.verify_on_save && event.verify().is_err() {
+                return Ok(SaveEventStatus::Rejected(RejectedReason::InvalidSignature));
             }
+
+            let DatabaseEventResult { status, .. } = self.helper.replace_if_newer(event).await;
+            Ok(status)
         })
     }
 
@@ -124,6 +579,21 @@ impl NostrEventsDatabase for MemoryDatabase {
         })
     }
 
+    fn has_event<'a>(
+        &'a self,
+        event_id: &'a EventId,
+    ) -> BoxedFuture<'a, Result<bool, DatabaseError>> {
+        Box::pin(async move {
+            if self.opts.events {
+                // Deleted events are removed from the store
+                Ok(self.helper.has_event(event_id).await)
+            } else {
+                let seen_event_ids = self.seen_event_ids.read().await;
+                Ok(seen_event_ids.contains(event_id))
+            }
+        })
+    }
+
     fn has_coordinate_been_deleted<'a>(
         &'a self,
         coordinate: &'a CoordinateBorrow<'a>,
@@ -148,8 +618,48 @@ impl NostrEventsDatabase for MemoryDatabase {
         Box::pin(async move { Ok(self.helper.count(filter).await) })
     }
 
+    fn count_by_filters(&self, filters: Vec<Filter>) -> BoxedFuture<Result<usize, DatabaseError>> {
+        Box::pin(async move { Ok(self.helper.count_by_filters(filters).await) })
+    }
+
     fn query(&self, filter: Filter) -> BoxedFuture<Result<Events, DatabaseError>> {
-        Box::pin(async move { Ok(self.helper.query(filter).await) })
+        Box::pin(async move {
+            let filter: Filter = cap_filter_limit(filter, self.opts.max_query_results);
+            Ok(self.helper.query(filter).await)
+        })
+    }
+
+    fn query_page(
+        &self,
+        filter: Filter,
+        cursor: Option<PageCursor>,
+        limit: usize,
+    ) -> BoxedFuture<Result<(Events, Option<PageCursor>), DatabaseError>> {
+        // Cap the page, not the internal queries, so the pages have no gaps
+        let limit: usize = self
+            .opts
+            .max_query_results
+            .map_or(limit, |max| limit.min(max));
+        Box::pin(query_page_with(
+            move |filter| async move { Ok(self.helper.query(filter).await) },
+            filter,
+            cursor,
+            limit,
+        ))
+    }
+
+    fn latest(
+        &self,
+        filter: Filter,
+    ) -> BoxedFuture<Result<Option<(EventId, Timestamp)>, DatabaseError>> {
+        Box::pin(async move { Ok(self.helper.latest(filter).await) })
+    }
+
+    fn event_ids(&self, filter: Filter) -> BoxedFuture<Result<BTreeSet<EventId>, DatabaseError>> {
+        Box::pin(async move {
+            let filter: Filter = cap_filter_limit(filter, self.opts.max_query_results);
+            Ok(self.helper.event_ids(filter).await)
+        })
     }
 
     fn negentropy_items(
@@ -159,11 +669,12 @@ impl NostrEventsDatabase for MemoryDatabase {
         Box::pin(async move { Ok(self.helper.negentropy_items(filter).await) })
     }
 
-    fn delete(&self, filter: Filter) -> BoxedFuture<Result<(), DatabaseError>> {
-        Box::pin(async move {
-            self.helper.delete(filter).await;
-            Ok(())
-        })
+    fn delete(&self, filter: Filter) -> BoxedFuture<Result<usize, DatabaseError>> {
+        Box::pin(async move { Ok(self.helper.delete_count(filter).await) })
+    }
+
+    fn prune(&self) -> BoxedFuture<Result<PruneReport, DatabaseError>> {
+        Box::pin(async move { Ok(self.helper.prune().await) })
     }
 }
 
@@ -179,6 +690,10 @@ impl NostrDatabaseWipe for MemoryDatabase {
             Ok(())
         })
     }
+
+    fn truncate_to(&self, keep_newest: usize) -> BoxedFuture<Result<usize, DatabaseError>> {
+        Box::pin(async move { Ok(self.helper.truncate_to(keep_newest).await) })
+    }
 }
 
 #[derive(Debug)]
@@ -245,7 +760,15 @@ impl SeenTracker {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeSet;
+
     use super::*;
+    use crate::PageCursor;
+
+    crate::database_unit_tests!(MemoryDatabase::with_opts(MemoryDatabaseOptions {
+        events: true,
+        ..Default::default()
+    }));
 
     #[test]
     fn test_seen_tracker_without_capacity() {
@@ -300,4 +823,1252 @@ mod tests {
         assert!(tracker.contains(&id2));
         assert!(tracker.queue.contains(&id2));
     }
+
+    #[tokio::test]
+    async fn test_max_query_results() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            max_query_results: Some(3),
+            ..Default::default()
+        });
+        let keys = Keys::generate();
+        let base = Timestamp::from_secs(1_700_000_000);
+
+        let mut events: Vec<Event> = Vec::new();
+        for i in 0..10 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .custom_created_at(base + i)
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+            events.push(event);
+        }
+
+        // Newest first
+        let expected: Vec<Event> = events.into_iter().rev().take(3).collect();
+
+        let stored = db.query(Filter::new().kind(Kind::TextNote)).await.unwrap();
+        assert_eq!(stored.to_vec(), expected);
+
+        // A bigger limit is capped too
+        let filter = Filter::new().kind(Kind::TextNote).limit(100);
+        assert_eq!(db.query(filter).await.unwrap().len(), 3);
+
+        // A smaller limit is kept
+        let filter = Filter::new().kind(Kind::TextNote).limit(2);
+        assert_eq!(db.query(filter).await.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_indexed_tags() {
+        let p = SingleLetterTag::lowercase(Alphabet::P);
+        let upper_z = SingleLetterTag::uppercase(Alphabet::Z);
+        let a = SingleLetterTag::lowercase(Alphabet::A);
+
+        let tags = IndexedTags::from_iter([upper_z, p]);
+        assert!(tags.contains(&p));
+        assert!(tags.contains(&upper_z));
+        assert!(!tags.contains(&a));
+        assert!(!tags.contains(&SingleLetterTag::uppercase(Alphabet::P)));
+        assert_eq!(tags.iter().collect::<Vec<_>>(), vec![p, upper_z]);
+
+        assert!(IndexedTags::new().is_empty());
+        assert!(!tags.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tag_index() {
+        let p = SingleLetterTag::lowercase(Alphabet::P);
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            index_tags: IndexedTags::from_iter([p]),
+            ..Default::default()
+        });
+
+        let keys: Vec<Keys> = (0..4).map(|_| Keys::generate()).collect();
+        let base = Timestamp::from_secs(1_700_000_000);
+
+        let mut events: Vec<Event> = Vec::new();
+        for i in 0..40usize {
+            let author: &Keys = &keys[i % keys.len()];
+            let mentioned: PublicKey = keys[(i * 7 + 1) % keys.len()].public_key;
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .tag(Tag::public_key(mentioned))
+                .custom_created_at(base + i as u64)
+                .sign_with_keys(author)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+            events.push(event);
+        }
+
+        // Delete some events: they must be removed from the index too
+        let filter = Filter::new().author(keys[0].public_key);
+        db.delete(filter).await.unwrap();
+        events.retain(|e| e.pubkey != keys[0].public_key);
+
+        for k in keys.iter() {
+            let filter = Filter::new()
+                .kind(Kind::TextNote)
+                .pubkey(k.public_key)
+                .since(base + 5);
+
+            // Brute-force baseline
+            let mut expected: Vec<Event> = events
+                .iter()
+                .filter(|e| filter.match_event(e))
+                .cloned()
+                .collect();
+            expected.sort();
+
+            assert_eq!(db.query(filter.clone()).await.unwrap().to_vec(), expected);
+            assert_eq!(db.count(filter).await.unwrap(), expected.len());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_import_jsonl() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+
+        let keys = Keys::generate();
+        let base = Timestamp::from_secs(1_700_000_000);
+
+        let note = EventBuilder::text_note("Text note")
+            .custom_created_at(base)
+            .sign_with_keys(&keys)
+            .unwrap();
+        let old_metadata = EventBuilder::metadata(&Metadata::new().name("old"))
+            .custom_created_at(base)
+            .sign_with_keys(&keys)
+            .unwrap();
+        let new_metadata = EventBuilder::metadata(&Metadata::new().name("new"))
+            .custom_created_at(base + 10)
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        let dump: String = [
+            note.as_json(),
+            new_metadata.as_json(),
+            String::from("{\"id\": \"malformed\""),
+            old_metadata.as_json(),
+            String::new(),
+            note.as_json(),
+        ]
+        .join("\n");
+
+        let report = db.import_jsonl(dump.as_bytes()).await.unwrap();
+        assert_eq!(
+            report,
+            ImportReport {
+                saved: 2,
+                duplicate: 1,
+                rejected: 1,
+                invalid_json: 1,
+            }
+        );
+
+        let events = db.query(Filter::new()).await.unwrap();
+        assert_eq!(events.to_vec(), vec![new_metadata, note]);
+    }
+
+    #[tokio::test]
+    async fn test_delete_count() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+        let keys = Keys::generate();
+
+        for i in 0..7 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+        }
+        let metadata = EventBuilder::metadata(&Metadata::new().name("name"))
+            .sign_with_keys(&keys)
+            .unwrap();
+        db.save_event(&metadata).await.unwrap();
+
+        let filter = Filter::new().kind(Kind::TextNote);
+        let existing: usize = db.count(filter.clone()).await.unwrap();
+        assert_eq!(existing, 7);
+
+        assert_eq!(db.delete(filter.clone()).await.unwrap(), existing);
+        assert_eq!(db.count(filter.clone()).await.unwrap(), 0);
+
+        // Nothing left to delete
+        assert_eq!(db.delete(filter).await.unwrap(), 0);
+
+        // Delete everything
+        assert_eq!(db.delete(Filter::new()).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_save_event_applies_in_call_order() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+
+        let keys = Keys::generate();
+
+        // Save target and deletion back-to-back: no batching, the deletion must see the target
+        let target = EventBuilder::text_note("Target")
+            .sign_with_keys(&keys)
+            .unwrap();
+        let deletion = EventBuilder::delete([target.id])
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        assert!(db.save_event(&target).await.unwrap().is_success());
+        assert!(db.save_event(&deletion).await.unwrap().is_success());
+
+        assert_eq!(
+            db.check_id(&target.id).await.unwrap(),
+            DatabaseEventStatus::Deleted
+        );
+        assert!(db.event_by_id(&target.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_query_with_options() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+
+        let keys = Keys::generate();
+        let base = Timestamp::from_secs(1_700_000_000);
+
+        let mut events: Vec<Event> = Vec::new();
+        for i in 0..10 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .custom_created_at(base + i)
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+            events.push(event);
+        }
+
+        let asc = QueryOptions::new().order(Order::Asc);
+        let desc = QueryOptions::new().order(Order::Desc);
+
+        // Without limit
+        let filter = Filter::new().author(keys.public_key);
+        assert_eq!(
+            db.query_with_options(filter.clone(), asc).await.unwrap(),
+            events
+        );
+        assert_eq!(
+            db.query_with_options(filter, desc).await.unwrap(),
+            events.iter().rev().cloned().collect::<Vec<_>>()
+        );
+
+        // With limit: the first N in the requested order
+        let filter = Filter::new().kind(Kind::TextNote).limit(3);
+        assert_eq!(
+            db.query_with_options(filter.clone(), asc).await.unwrap(),
+            events[..3].to_vec()
+        );
+        assert_eq!(
+            db.query_with_options(filter, desc).await.unwrap(),
+            events.iter().rev().take(3).cloned().collect::<Vec<_>>()
+        );
+
+        // Empty filter with limit
+        let filter = Filter::new().limit(2);
+        assert_eq!(
+            db.query_with_options(filter, asc).await.unwrap(),
+            events[..2].to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_with_options_max_query_results() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            max_query_results: Some(3),
+            ..Default::default()
+        });
+
+        let keys = Keys::generate();
+        let base = Timestamp::from_secs(1_700_000_000);
+
+        let mut events: Vec<Event> = Vec::new();
+        for i in 0..10 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .custom_created_at(base + i)
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+            events.push(event);
+        }
+
+        // Capped in the requested order
+        let filter = Filter::new().author(keys.public_key);
+        assert_eq!(
+            db.query_with_options(filter.clone(), QueryOptions::new().order(Order::Asc))
+                .await
+                .unwrap(),
+            events[..3].to_vec()
+        );
+        assert_eq!(
+            db.query_with_options(filter, QueryOptions::new().order(Order::Desc))
+                .await
+                .unwrap(),
+            events.iter().rev().take(3).cloned().collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_has_event() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+        let keys = Keys::generate();
+
+        let existing = EventBuilder::text_note("Existing")
+            .sign_with_keys(&keys)
+            .unwrap();
+        let deleted = EventBuilder::text_note("Deleted")
+            .sign_with_keys(&keys)
+            .unwrap();
+        let absent = EventBuilder::text_note("Absent")
+            .sign_with_keys(&keys)
+            .unwrap();
+        let deletion = EventBuilder::delete([deleted.id])
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        db.save_event(&existing).await.unwrap();
+        db.save_event(&deleted).await.unwrap();
+        db.save_event(&deletion).await.unwrap();
+
+        assert!(db.has_event(&existing.id).await.unwrap());
+        assert!(!db.has_event(&absent.id).await.unwrap());
+
+        assert_eq!(
+            db.check_id(&deleted.id).await.unwrap(),
+            DatabaseEventStatus::Deleted
+        );
+        assert!(!db.has_event(&deleted.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_tie_breaker_first_seen() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            tie_breaker: TieBreaker::FirstSeen,
+            ..Default::default()
+        });
+
+        let keys = Keys::generate();
+        let created_at = Timestamp::from_secs(1_700_000_000);
+
+        let a = EventBuilder::metadata(&Metadata::new().name("a"))
+            .custom_created_at(created_at)
+            .sign_with_keys(&keys)
+            .unwrap();
+        let b = EventBuilder::metadata(&Metadata::new().name("b"))
+            .custom_created_at(created_at)
+            .sign_with_keys(&keys)
+            .unwrap();
+        let (lower, higher) = if a.id < b.id { (a, b) } else { (b, a) };
+
+        // Store the event with the higher ID first
+        assert_eq!(
+            db.save_event(&higher).await.unwrap(),
+            SaveEventStatus::Success
+        );
+        assert_eq!(
+            db.save_event(&lower).await.unwrap(),
+            SaveEventStatus::Rejected(RejectedReason::Replaced)
+        );
+
+        let filter = Filter::new().author(keys.public_key).kind(Kind::Metadata);
+        let events = db.query(filter).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events.first().unwrap().id, higher.id);
+
+        // A newer event still replaces it
+        let newer = EventBuilder::metadata(&Metadata::new().name("newer"))
+            .custom_created_at(created_at + 1)
+            .sign_with_keys(&keys)
+            .unwrap();
+        assert_eq!(
+            db.save_event(&newer).await.unwrap(),
+            SaveEventStatus::Success
+        );
+        assert!(!db.has_event(&higher.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_wipe_filter() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+
+        let keys_a = Keys::generate();
+        let keys_b = Keys::generate();
+
+        let note_a = EventBuilder::text_note("Note A")
+            .sign_with_keys(&keys_a)
+            .unwrap();
+        let deleted_a = EventBuilder::text_note("Deleted A")
+            .sign_with_keys(&keys_a)
+            .unwrap();
+        let deletion_a = EventBuilder::delete([deleted_a.id])
+            .sign_with_keys(&keys_a)
+            .unwrap();
+        let note_b = EventBuilder::text_note("Note B")
+            .sign_with_keys(&keys_b)
+            .unwrap();
+
+        for event in [&note_a, &deleted_a, &deletion_a, &note_b] {
+            db.save_event(event).await.unwrap();
+        }
+        assert_eq!(
+            db.save_event(&deleted_a).await.unwrap(),
+            SaveEventStatus::Rejected(RejectedReason::Deleted)
+        );
+
+        // Wipe author A
+        let filter = Filter::new().author(keys_a.public_key);
+        assert_eq!(db.wipe_filter(filter.clone()).await, 2);
+        assert_eq!(db.count(filter).await.unwrap(), 0);
+
+        // Author B is untouched
+        assert!(db.has_event(&note_b.id).await.unwrap());
+
+        // The events of author A can be saved again
+        assert_eq!(
+            db.check_id(&deleted_a.id).await.unwrap(),
+            DatabaseEventStatus::NotExistent
+        );
+        assert_eq!(
+            db.save_event(&deleted_a).await.unwrap(),
+            SaveEventStatus::Success
+        );
+        assert_eq!(
+            db.save_event(&note_a).await.unwrap(),
+            SaveEventStatus::Success
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wipe_filter_replaced() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+        let keys = Keys::generate();
+
+        let metadata = |name: &str, secs: u64| {
+            EventBuilder::metadata(&Metadata::new().name(name))
+                .custom_created_at(Timestamp::from_secs(secs))
+                .sign_with_keys(&keys)
+                .unwrap()
+        };
+
+        let old = metadata("old", 1000);
+        let new = metadata("new", 2000);
+        db.save_event(&old).await.unwrap();
+        db.save_event(&new).await.unwrap();
+        assert!(!db.has_event(&old.id).await.unwrap());
+
+        // Wipe the author, also forgetting the replaced event
+        let filter = Filter::new().author(keys.public_key);
+        assert_eq!(db.wipe_filter(filter).await, 1);
+
+        assert_eq!(db.save_event(&old).await.unwrap(), SaveEventStatus::Success);
+        assert!(db.has_event(&old.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_count_approx() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            max_events: None,
+            ..Default::default()
+        });
+
+        let keys: Vec<Keys> = (0..50).map(|_| Keys::generate()).collect();
+        let kinds: [Kind; 4] = [Kind::TextNote, Kind::Repost, Kind::Reaction, Kind::Comment];
+        let base = Timestamp::from_secs(1_700_000_000);
+
+        let mut events: BTreeSet<Event> = BTreeSet::new();
+        for i in 0..10_000u64 {
+            // Skewed distributions
+            let keys: &Keys = &keys[(i * i) as usize % keys.len()];
+            let kind: Kind = kinds[(i % 7) as usize % kinds.len()];
+            let event = EventBuilder::new(kind, format!("Event {i}"))
+                .custom_created_at(base + i)
+                .sign_with_keys(keys)
+                .unwrap();
+            events.insert(event);
+        }
+        db.helper.bulk_import(events).await;
+
+        let error_rate: f64 = 0.01;
+        let total: usize = db.count(Filter::new()).await.unwrap();
+        assert_eq!(total, 10_000);
+        let max_error: usize = (error_rate * total as f64) as usize;
+
+        let check = |filter: Filter| async {
+            let exact: usize = db.count(filter.clone()).await.unwrap();
+            let approx: usize = db.count_approx(filter, error_rate).await.unwrap();
+            assert!(approx >= exact);
+            assert!(approx <= exact + max_error);
+        };
+
+        check(Filter::new()).await;
+        for keys in keys.iter() {
+            check(Filter::new().author(keys.public_key)).await;
+        }
+        for kind in kinds.into_iter() {
+            check(Filter::new().kind(kind)).await;
+        }
+        check(Filter::new().authors(keys.iter().take(5).map(|k| k.public_key))).await;
+
+        // Removals are tracked
+        let filter = Filter::new().author(keys[0].public_key);
+        db.delete(filter.clone()).await.unwrap();
+        assert!(db.count_approx(filter, error_rate).await.unwrap() <= max_error);
+
+        // Not supported by the sketch: exact
+        let filter = Filter::new()
+            .author(keys[1].public_key)
+            .kind(Kind::TextNote);
+        assert_eq!(
+            db.count_approx(filter.clone(), error_rate).await.unwrap(),
+            db.count(filter).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_truncate_to() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+        let keys = Keys::generate();
+        let base = Timestamp::from_secs(1_700_000_000);
+
+        let mut events: Vec<Event> = Vec::new();
+        for i in 0..10 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .custom_created_at(base + i)
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+            events.push(event);
+        }
+
+        assert_eq!(db.truncate_to(3).await.unwrap(), 7);
+
+        // The 3 newest remain
+        let stored = db.query(Filter::new()).await.unwrap();
+        let ids: Vec<EventId> = stored.into_iter().map(|e| e.id).collect();
+        let expected: Vec<EventId> = events.iter().rev().take(3).map(|e| e.id).collect();
+        assert_eq!(ids, expected);
+
+        // Nothing else to remove
+        assert_eq!(db.truncate_to(3).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_query_many_ids_with_limit() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+
+        let keys = Keys::generate();
+        let base = Timestamp::from_secs(1_700_000_000);
+
+        let mut events: Vec<Event> = Vec::new();
+        for i in 0..200 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                // Some events share the same timestamp
+                .custom_created_at(base + i / 3)
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+            events.push(event);
+        }
+
+        // Every other event, plus some unknown IDs
+        let mut ids: Vec<EventId> = events.iter().step_by(2).map(|e| e.id).collect();
+        ids.extend((0..100u8).map(|i| EventId::from_byte_array([i; 32])));
+
+        let filter = Filter::new().ids(ids.clone()).limit(10);
+        let result: Vec<EventId> = db
+            .query(filter.clone())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.id)
+            .collect();
+
+        // Brute force
+        let mut expected: Vec<Event> = events
+            .iter()
+            .filter(|e| ids.contains(&e.id))
+            .cloned()
+            .collect();
+        // Lookup ID: EVENT_ORD_IMPL
+        expected.sort();
+        let expected: Vec<EventId> = expected.into_iter().take(10).map(|e| e.id).collect();
+
+        assert_eq!(result, expected);
+
+        // Combined with other conditions
+        let filter = filter.kind(Kind::Metadata);
+        assert!(db.query(filter).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_max_deletion_records() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            max_deletion_records: NonZeroUsize::new(1),
+            ..Default::default()
+        });
+        let keys = Keys::generate();
+
+        let first = EventBuilder::text_note("First")
+            .sign_with_keys(&keys)
+            .unwrap();
+        let second = EventBuilder::text_note("Second")
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        db.save_event(&first).await.unwrap();
+        db.save_event(&second).await.unwrap();
+
+        let deletion = EventBuilder::delete([first.id])
+            .sign_with_keys(&keys)
+            .unwrap();
+        db.save_event(&deletion).await.unwrap();
+        assert_eq!(
+            db.check_id(&first.id).await.unwrap(),
+            DatabaseEventStatus::Deleted
+        );
+
+        // The second deletion evicts the oldest record
+        let deletion = EventBuilder::delete([second.id])
+            .sign_with_keys(&keys)
+            .unwrap();
+        db.save_event(&deletion).await.unwrap();
+        assert_eq!(
+            db.check_id(&first.id).await.unwrap(),
+            DatabaseEventStatus::NotExistent
+        );
+        assert_eq!(
+            db.check_id(&second.id).await.unwrap(),
+            DatabaseEventStatus::Deleted
+        );
+
+        // The forgotten event can be saved again
+        assert!(db.save_event(&first).await.unwrap().is_success());
+    }
+
+    #[tokio::test]
+    async fn test_verify_on_save() {
+        let keys = Keys::generate();
+        let event = EventBuilder::text_note("Original")
+            .sign_with_keys(&keys)
+            .unwrap();
+        let other = EventBuilder::text_note("Other")
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        // Corrupt the signature
+        let corrupted = Event::new(
+            event.id,
+            event.pubkey,
+            event.created_at,
+            event.kind,
+            event.tags.clone(),
+            event.content.clone(),
+            other.sig,
+        );
+
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            verify_on_save: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            db.save_event(&corrupted).await.unwrap(),
+            SaveEventStatus::Rejected(RejectedReason::InvalidSignature)
+        );
+        assert!(db.save_event(&event).await.unwrap().is_success());
+
+        // Trusted by default
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+        assert!(db.save_event(&corrupted).await.unwrap().is_success());
+    }
+
+    #[tokio::test]
+    async fn test_event_by_ids() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+        let keys = Keys::generate();
+
+        let first = EventBuilder::text_note("First")
+            .sign_with_keys(&keys)
+            .unwrap();
+        let second = EventBuilder::text_note("Second")
+            .sign_with_keys(&keys)
+            .unwrap();
+        let missing = EventBuilder::text_note("Missing")
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        db.save_event(&first).await.unwrap();
+        db.save_event(&second).await.unwrap();
+
+        let events = db
+            .event_by_ids(&[second.id, missing.id, first.id])
+            .await
+            .unwrap();
+        assert_eq!(events, vec![Some(second), None, Some(first)]);
+    }
+
+    #[tokio::test]
+    async fn test_query_multi_letter_tags() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+        let keys = Keys::generate();
+
+        let app = EventBuilder::text_note("From app")
+            .tag(Tag::parse(["client", "app"]).unwrap())
+            .sign_with_keys(&keys)
+            .unwrap();
+        let other = EventBuilder::text_note("From other")
+            .tag(Tag::parse(["client", "other"]).unwrap())
+            .sign_with_keys(&keys)
+            .unwrap();
+        let untagged = EventBuilder::text_note("Untagged")
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        db.save_event(&app).await.unwrap();
+        db.save_event(&other).await.unwrap();
+        db.save_event(&untagged).await.unwrap();
+
+        let filter = Filter::new().multi_letter_tag("client", "app");
+        let events = db.query(filter).await.unwrap();
+        assert_eq!(events.to_vec(), vec![app.clone()]);
+
+        // Also with a pattern that would use the author index
+        let filter = Filter::new()
+            .author(keys.public_key)
+            .multi_letter_tag("client", "app");
+        let events = db.query(filter).await.unwrap();
+        assert_eq!(events.to_vec(), vec![app]);
+    }
+
+    #[test]
+    fn test_handled_kinds() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+        let kinds = db.handled_kinds();
+        assert!(kinds.replaceable);
+        assert!(kinds.addressable);
+        assert!(kinds.nip09);
+        assert!(!kinds.nip40);
+        assert!(!kinds.nip62);
+
+        // Nothing is stored
+        let db = MemoryDatabase::new();
+        assert_eq!(db.handled_kinds(), HandledKinds::default());
+    }
+
+    #[tokio::test]
+    async fn test_delete_with_tombstone() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+        let keys = Keys::generate();
+
+        let tombstoned = EventBuilder::text_note("Tombstoned")
+            .sign_with_keys(&keys)
+            .unwrap();
+        let deleted = EventBuilder::text_note("Deleted")
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        db.save_event(&tombstoned).await.unwrap();
+        db.save_event(&deleted).await.unwrap();
+
+        assert_eq!(
+            db.delete_with_tombstone(Filter::new().id(tombstoned.id))
+                .await,
+            1
+        );
+        assert_eq!(db.delete(Filter::new().id(deleted.id)).await.unwrap(), 1);
+
+        assert_eq!(
+            db.save_event(&tombstoned).await.unwrap(),
+            SaveEventStatus::Rejected(RejectedReason::Deleted)
+        );
+
+        // Plain delete doesn't mark as deleted
+        assert!(db.save_event(&deleted).await.unwrap().is_success());
+    }
+
+    #[tokio::test]
+    async fn test_save_event_full_replaced() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+
+        let keys = Keys::generate();
+        let first = EventBuilder::metadata(&Metadata::new().name("first"))
+            .custom_created_at(Timestamp::from_secs(1000))
+            .sign_with_keys(&keys)
+            .unwrap();
+        let second = EventBuilder::metadata(&Metadata::new().name("second"))
+            .custom_created_at(Timestamp::from_secs(2000))
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        let outcome = db.save_event_full(&first).await.unwrap();
+        assert!(outcome.status.is_success());
+        assert!(outcome.replaced.is_empty());
+
+        let outcome = db.save_event_full(&second).await.unwrap();
+        assert!(outcome.status.is_success());
+        assert_eq!(outcome.replaced, vec![first.id]);
+
+        // Older metadata is rejected and replaces nothing
+        let older = EventBuilder::metadata(&Metadata::new().name("older"))
+            .custom_created_at(Timestamp::from_secs(500))
+            .sign_with_keys(&keys)
+            .unwrap();
+        let outcome = db.save_event_full(&older).await.unwrap();
+        assert_eq!(
+            outcome.status,
+            SaveEventStatus::Rejected(RejectedReason::Replaced)
+        );
+        assert!(outcome.replaced.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_query_page() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+
+        // Pairs of events share the same timestamp
+        let keys = Keys::generate();
+        for i in 0..10 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .custom_created_at(Timestamp::from_secs(1000 + i / 2))
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+        }
+
+        let expected: Vec<EventId> = db
+            .query(Filter::new())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.id)
+            .collect();
+        assert_eq!(expected.len(), 10);
+
+        let mut ids: Vec<EventId> = Vec::new();
+        let mut cursor: Option<PageCursor> = None;
+        let mut pages: usize = 0;
+        loop {
+            let (events, next) = db.query_page(Filter::new(), cursor, 3).await.unwrap();
+            assert!(events.len() <= 3);
+            ids.extend(events.into_iter().map(|e| e.id));
+            pages += 1;
+
+            match next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        assert_eq!(pages, 4);
+        assert_eq!(ids, expected);
+    }
+
+    #[tokio::test]
+    async fn test_query_page_max_query_results() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            max_query_results: Some(2),
+            ..Default::default()
+        });
+
+        // Pairs of events share the same timestamp
+        let keys = Keys::generate();
+        let mut events: Vec<Event> = Vec::new();
+        for i in 0..10 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .custom_created_at(Timestamp::from_secs(1000 + i / 2))
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+            events.push(event);
+        }
+        events.sort();
+
+        // The page size is above the cap
+        let mut ids: Vec<EventId> = Vec::new();
+        let mut cursor: Option<PageCursor> = None;
+        loop {
+            let (page, next) = db.query_page(Filter::new(), cursor, 3).await.unwrap();
+            assert!(page.len() <= 2);
+            ids.extend(page.into_iter().map(|e| e.id));
+
+            match next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        let expected: Vec<EventId> = events.into_iter().map(|e| e.id).collect();
+        assert_eq!(ids, expected);
+    }
+
+    #[tokio::test]
+    async fn test_replace_if_newer() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+        let keys = Keys::generate();
+
+        let metadata = |name: &str, secs: u64| {
+            EventBuilder::metadata(&Metadata::new().name(name))
+                .custom_created_at(Timestamp::from_secs(secs))
+                .sign_with_keys(&keys)
+                .unwrap()
+        };
+
+        let stored = metadata("stored", 2000);
+        assert!(db.replace_if_newer(&stored).await.unwrap().is_success());
+
+        // Older and same timestamp are rejected, regardless of the ID
+        for event in [metadata("older", 1000), metadata("tie", 2000)] {
+            assert_eq!(
+                db.replace_if_newer(&event).await.unwrap(),
+                SaveEventStatus::Rejected(RejectedReason::Replaced)
+            );
+        }
+
+        // Concurrent saves: only the newest is kept
+        let older = metadata("concurrent older", 2500);
+        let newer = metadata("concurrent newer", 3000);
+        let (a, b) = tokio::join!(db.replace_if_newer(&newer), db.replace_if_newer(&older));
+        assert!(a.unwrap().is_success());
+        assert!(!b.unwrap().is_success());
+
+        let events = db
+            .query(Filter::new().author(keys.public_key).kind(Kind::Metadata))
+            .await
+            .unwrap();
+        assert_eq!(events.to_vec(), vec![newer]);
+    }
+
+    #[tokio::test]
+    async fn test_query_search_relevance() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+        let keys = Keys::generate();
+
+        let earlier = EventBuilder::text_note("Nostr, nostr and more NOSTR")
+            .custom_created_at(Timestamp::from_secs(1000))
+            .sign_with_keys(&keys)
+            .unwrap();
+        let later = EventBuilder::text_note("Just a mention of nostr")
+            .custom_created_at(Timestamp::from_secs(2000))
+            .sign_with_keys(&keys)
+            .unwrap();
+        let unrelated = EventBuilder::text_note("Hello world")
+            .custom_created_at(Timestamp::from_secs(3000))
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        for event in [&earlier, &later, &unrelated] {
+            db.save_event(event).await.unwrap();
+        }
+
+        let filter = Filter::new().search("nostr");
+
+        // Default: newest first
+        let events = db
+            .query_search(filter.clone(), SearchOptions::new())
+            .await
+            .unwrap();
+        assert_eq!(events, vec![later.clone(), earlier.clone()]);
+
+        let opts = SearchOptions::new().rank(SearchRank::Relevance);
+        let events = db.query_search(filter.clone(), opts).await.unwrap();
+        assert_eq!(events, vec![earlier.clone(), later]);
+
+        // Limit applied after the ranking
+        let events = db.query_search(filter.limit(1), opts).await.unwrap();
+        assert_eq!(events, vec![earlier]);
+    }
+
+    #[tokio::test]
+    async fn test_query_search_max_query_results() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            max_query_results: Some(1),
+            ..Default::default()
+        });
+        let keys = Keys::generate();
+
+        let earlier = EventBuilder::text_note("Nostr, nostr and more NOSTR")
+            .custom_created_at(Timestamp::from_secs(1000))
+            .sign_with_keys(&keys)
+            .unwrap();
+        let later = EventBuilder::text_note("Just a mention of nostr")
+            .custom_created_at(Timestamp::from_secs(2000))
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        for event in [&earlier, &later] {
+            db.save_event(event).await.unwrap();
+        }
+
+        let filter = Filter::new().search("nostr");
+
+        let events = db
+            .query_search(filter.clone(), SearchOptions::new())
+            .await
+            .unwrap();
+        assert_eq!(events, vec![later]);
+
+        // Capped after the ranking
+        let opts = SearchOptions::new().rank(SearchRank::Relevance);
+        let events = db.query_search(filter, opts).await.unwrap();
+        assert_eq!(events, vec![earlier]);
+    }
+
+    #[test]
+    fn test_backend_is_persistent() {
+        let db = MemoryDatabase::new();
+        assert_eq!(db.backend(), Backend::Memory);
+        assert!(!db.backend().is_persistent());
+    }
+
+    #[tokio::test]
+    async fn test_query_filtered() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+
+        let muted = Keys::generate();
+        let other = Keys::generate();
+
+        for i in 0..3 {
+            for keys in [&muted, &other] {
+                let event = EventBuilder::text_note(format!("Note {i}"))
+                    .sign_with_keys(keys)
+                    .unwrap();
+                db.save_event(&event).await.unwrap();
+            }
+        }
+
+        let events = db
+            .query_filtered(Filter::new(), |pk| pk != &muted.public_key)
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 3);
+        assert!(events.iter().all(|e| e.pubkey == other.public_key));
+
+        // The limit is applied after the predicate
+        let events = db
+            .query_filtered(Filter::new().limit(2), |pk| pk != &muted.public_key)
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|e| e.pubkey == other.public_key));
+    }
+
+    #[tokio::test]
+    async fn test_clone_shares_store() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+        let cloned = db.clone();
+
+        let keys = Keys::generate();
+        let event = EventBuilder::text_note("Shared")
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        let handle = tokio::spawn(async move { cloned.save_event(&event).await.unwrap() });
+        assert!(handle.await.unwrap().is_success());
+
+        assert_eq!(db.count(Filter::new()).await.unwrap(), 1);
+
+        // Also without storing events
+        let db = MemoryDatabase::new();
+        let cloned = db.clone();
+        let event = EventBuilder::text_note("Seen")
+            .sign_with_keys(&keys)
+            .unwrap();
+        cloned.save_event(&event).await.unwrap();
+        assert_eq!(
+            db.check_id(&event.id).await.unwrap(),
+            DatabaseEventStatus::Saved
+        );
+    }
+
+    #[tokio::test]
+    async fn test_count_with_limit() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+
+        let keys = Keys::generate();
+        for i in 0..10 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+        }
+
+        let filter = Filter::new().author(keys.public_key).kind(Kind::TextNote);
+        assert_eq!(db.count(filter.clone()).await.unwrap(), 10);
+        assert_eq!(db.count(filter.clone().limit(3)).await.unwrap(), 3);
+        assert_eq!(db.count(filter.limit(20)).await.unwrap(), 10);
+        assert_eq!(db.count(Filter::new().limit(3)).await.unwrap(), 3);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn test_tracing_spans() {
+        use std::fmt;
+        use std::sync::{Arc, Mutex};
+
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Id, Record};
+        use tracing::Subscriber;
+        use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+        use tracing_subscriber::registry::LookupSpan;
+
+        type Results = Arc<Mutex<Vec<(&'static str, u64)>>>;
+
+        /// Record the `results` fields of the spans
+        struct Recorder(Results);
+
+        struct ResultsVisitor<'a>(&'static str, &'a Results);
+
+        impl Visit for ResultsVisitor<'_> {
+            fn record_u64(&mut self, field: &Field, value: u64) {
+                if field.name() == "results" {
+                    self.1.lock().unwrap().push((self.0, value));
+                }
+            }
+
+            fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+        }
+
+        impl<S> Layer<S> for Recorder
+        where
+            S: Subscriber + for<'a> LookupSpan<'a>,
+        {
+            fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+                if let Some(span) = ctx.span(id) {
+                    values.record(&mut ResultsVisitor(span.name(), &self.0));
+                }
+            }
+        }
+
+        let results: Results = Results::default();
+        let subscriber = tracing_subscriber::registry().with(Recorder(results.clone()));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+
+        let keys = Keys::generate();
+        for i in 0..5 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+        }
+
+        let filter = Filter::new().author(keys.public_key);
+        assert_eq!(db.query(filter.clone().limit(3)).await.unwrap().len(), 3);
+        assert_eq!(db.count(filter.clone()).await.unwrap(), 5);
+        assert_eq!(db.delete(filter).await.unwrap(), 5);
+
+        assert_eq!(
+            results.lock().unwrap().as_slice(),
+            &[("query", 3), ("count", 5), ("delete", 5)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_event_ids() {
+        let db = MemoryDatabase::with_opts(MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        });
+
+        let keys = Keys::generate();
+        let base = Timestamp::from_secs(1_700_000_000);
+        for i in 0..10 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .custom_created_at(base + i)
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+        }
+
+        for filter in [
+            Filter::new().author(keys.public_key),
+            Filter::new().kind(Kind::TextNote).limit(3),
+            Filter::new().since(base + 5),
+            Filter::new().kind(Kind::Metadata),
+        ] {
+            let expected: BTreeSet<EventId> = db
+                .query(filter.clone())
+                .await
+                .unwrap()
+                .iter()
+                .map(|e| e.id)
+                .collect();
+            assert_eq!(db.event_ids(filter).await.unwrap(), expected);
+        }
+    }
 }
@@ -0,0 +1,310 @@
+// Copyright (c) 2022-2023 Yuki Kishimoto
+// Copyright (c) 2023-2025 Rust Nostr Developers
+// Distributed under the MIT software license
+
+//! Backend test suite
+//!
+//! Shared tests of the [`NostrEventsDatabase`](crate::NostrEventsDatabase) behavior,
+//! run against every backend with [`database_unit_tests!`](crate::database_unit_tests).
+
+#![doc(hidden)]
+
+use std::collections::HashMap;
+
+use nostr::nips::nip01::Coordinate;
+use nostr::{
+    Event, EventBuilder, EventId, Keys, Kind, Metadata, PublicKey, SecretKey, Tag, Timestamp,
+};
+
+/// Deterministic pseudo-random number generator (xorshift64*)
+pub struct Rng(pub u64);
+
+impl Rng {
+    /// Next pseudo-random number
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Next pseudo-random number in `0..n`
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// Generate a random sequence of events and compute the expected visible set (newest first)
+///
+/// Timestamps are strictly increasing, so every event is newer than the previous ones.
+pub fn generate_deletion_scenario(seed: u64, len: usize) -> (Vec<Event>, Vec<Event>) {
+    const ADDRESSABLE: Kind = Kind::Custom(30_000);
+    const IDENTIFIERS: [&str; 2] = ["a", "b"];
+
+    let keys: Vec<Keys> = (1..=3u8)
+        .map(|i| Keys::new(SecretKey::from_slice(&[i; 32]).unwrap()))
+        .collect();
+    let base = Timestamp::from_secs(1_700_000_000);
+
+    let mut rng = Rng(seed);
+    let mut generated: Vec<Event> = Vec::with_capacity(len);
+    let mut visible: HashMap<EventId, Event> = HashMap::new();
+
+    for i in 0..len {
+        let author: &Keys = &keys[rng.below(keys.len())];
+
+        let builder: EventBuilder = match rng.below(5) {
+            0 => EventBuilder::text_note(format!("note-{i}")),
+            1 => EventBuilder::metadata(&Metadata::new().name(format!("name-{i}"))),
+            2 => EventBuilder::new(ADDRESSABLE, format!("addressable-{i}"))
+                .tag(Tag::identifier(IDENTIFIERS[rng.below(IDENTIFIERS.len())])),
+            3 if !generated.is_empty() => {
+                let target: &Event = &generated[rng.below(generated.len())];
+                EventBuilder::delete([target.id])
+            }
+            _ => {
+                let public_key: PublicKey = keys[rng.below(keys.len())].public_key;
+                let coordinate: Coordinate = Coordinate::new(ADDRESSABLE, public_key)
+                    .identifier(IDENTIFIERS[rng.below(IDENTIFIERS.len())]);
+                EventBuilder::delete([coordinate])
+            }
+        };
+
+        let event: Event = builder
+            .custom_created_at(base + i as u64)
+            .sign_with_keys(author)
+            .unwrap();
+
+        match event.kind {
+            Kind::Metadata => {
+                visible.retain(|_, e| !(e.kind == event.kind && e.pubkey == event.pubkey));
+            }
+            kind if kind == ADDRESSABLE => {
+                let identifier: Option<&str> = event.tags.identifier();
+                visible.retain(|_, e| {
+                    !(e.kind == event.kind
+                        && e.pubkey == event.pubkey
+                        && e.tags.identifier() == identifier)
+                });
+            }
+            Kind::EventDeletion => {
+                // Deleting events or coordinates of other authors is invalid
+                let invalid: bool = event.tags.event_ids().any(|id| {
+                    visible
+                        .get(id)
+                        .is_some_and(|target| target.pubkey != event.pubkey)
+                }) || event
+                    .tags
+                    .coordinates()
+                    .any(|c| c.public_key != event.pubkey);
+
+                if invalid {
+                    generated.push(event);
+                    continue;
+                }
+
+                for id in event.tags.event_ids() {
+                    visible.remove(id);
+                }
+
+                for coordinate in event.tags.coordinates() {
+                    visible.retain(|_, e| {
+                        !(e.kind == coordinate.kind
+                            && e.pubkey == coordinate.public_key
+                            && e.tags.identifier() == Some(coordinate.identifier.as_str()))
+                    });
+                }
+            }
+            _ => {}
+        }
+
+        visible.insert(event.id, event.clone());
+        generated.push(event);
+    }
+
+    let mut expected: Vec<Event> = visible.into_values().collect();
+    expected.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+    (generated, expected)
+}
+
+/// Generate the shared backend tests
+///
+/// Must be called inside a `#[cfg(test)]` module, with an expression that builds a new empty database
+/// (or a type that derefs to it). The calling crate needs `tokio` with the `macros` and `rt-multi-thread` features.
+#[macro_export]
+macro_rules! database_unit_tests {
+    ($new:expr) => {
+        #[tokio::test(flavor = "multi_thread")]
+        async fn test_concurrent_replaceable_events_convergence() {
+            use std::sync::Arc;
+
+            use $crate::nostr::{Event, EventBuilder, Filter, Keys, Kind, Metadata, Timestamp};
+            use $crate::NostrEventsDatabase;
+
+            let keys = Keys::generate();
+            let base = Timestamp::from_secs(1_700_000_000);
+
+            // Pairs of events share the same timestamp
+            let events: Vec<Event> = (0..10u64)
+                .map(|i| {
+                    EventBuilder::metadata(&Metadata::new().name(format!("name-{i}")))
+                        .custom_created_at(base + i / 2)
+                        .sign_with_keys(&keys)
+                        .unwrap()
+                })
+                .collect();
+
+            // Newest timestamp, lowest ID
+            let expected: Event = events
+                .iter()
+                .min_by(|a, b| b.created_at.cmp(&a.created_at).then(a.id.cmp(&b.id)))
+                .cloned()
+                .unwrap();
+
+            let forward: Vec<Event> = events.clone();
+            let reverse: Vec<Event> = events.iter().rev().cloned().collect();
+            let interleaved: Vec<Event> = events
+                .iter()
+                .step_by(2)
+                .chain(events.iter().skip(1).step_by(2))
+                .cloned()
+                .collect();
+
+            for order in [forward, reverse, interleaved] {
+                let db = Arc::new($new);
+
+                // Save all the events concurrently
+                let handles: Vec<_> = order
+                    .into_iter()
+                    .map(|event| {
+                        let db = db.clone();
+                        tokio::spawn(async move { db.save_event(&event).await.unwrap() })
+                    })
+                    .collect();
+
+                for handle in handles {
+                    handle.await.unwrap();
+                }
+
+                let events = db
+                    .query(Filter::new().author(keys.public_key).kind(Kind::Metadata))
+                    .await
+                    .unwrap();
+                assert_eq!(events.to_vec(), vec![expected.clone()]);
+            }
+        }
+
+        #[tokio::test]
+        async fn test_count_by_filters() {
+            use $crate::nostr::{EventBuilder, Filter, Keys, Kind};
+            use $crate::NostrEventsDatabase;
+
+            let db = $new;
+            let keys_a = Keys::generate();
+            let keys_b = Keys::generate();
+
+            for (keys, content) in [(&keys_a, "A1"), (&keys_a, "A2"), (&keys_b, "B1")] {
+                let event = EventBuilder::text_note(content)
+                    .sign_with_keys(keys)
+                    .unwrap();
+                db.save_event(&event).await.unwrap();
+            }
+
+            // Overlapping filters: a naive sum would be 5
+            let filters = vec![
+                Filter::new().author(keys_a.public_key).kind(Kind::TextNote),
+                Filter::new().kind(Kind::TextNote),
+            ];
+            assert_eq!(db.count_by_filters(filters).await.unwrap(), 3);
+
+            // Empty filter matches everything
+            let filters = vec![Filter::new().author(keys_b.public_key), Filter::new()];
+            assert_eq!(db.count_by_filters(filters).await.unwrap(), 3);
+        }
+
+        #[tokio::test]
+        async fn test_latest() {
+            use $crate::nostr::{Event, EventBuilder, Filter, Keys, Kind, Timestamp};
+            use $crate::NostrEventsDatabase;
+
+            let db = $new;
+            let keys = Keys::generate();
+            let base = Timestamp::from_secs(1_700_000_000);
+
+            let mut events: Vec<Event> = Vec::new();
+            for (i, offset) in [0, 30, 10, 30, 20].into_iter().enumerate() {
+                let event = EventBuilder::text_note(format!("Note {i}"))
+                    .custom_created_at(base + offset)
+                    .sign_with_keys(&keys)
+                    .unwrap();
+                db.save_event(&event).await.unwrap();
+                events.push(event);
+            }
+
+            // Two events share the newest timestamp: the lowest ID wins
+            let expected = events
+                .iter()
+                .filter(|e| e.created_at == base + 30)
+                .map(|e| (e.id, e.created_at))
+                .min()
+                .unwrap();
+
+            let filter = Filter::new().author(keys.public_key).kind(Kind::TextNote);
+            assert_eq!(db.latest(filter).await.unwrap(), Some(expected));
+
+            let filter = Filter::new().author(Keys::generate().public_key);
+            assert_eq!(db.latest(filter).await.unwrap(), None);
+        }
+
+        #[tokio::test]
+        async fn test_empty_tags() {
+            use $crate::nostr::{Alphabet, EventBuilder, Filter, Keys, SingleLetterTag, Tag};
+            use $crate::NostrEventsDatabase;
+
+            let db = $new;
+            let keys = Keys::generate();
+
+            let event = EventBuilder::text_note("Tagged")
+                .tag(Tag::public_key(Keys::generate().public_key))
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+
+            // A tag with no values matches no event, also when combined with an indexed field
+            for filter in [
+                Filter::new(),
+                Filter::new().author(keys.public_key),
+                Filter::new().author(keys.public_key).kind(event.kind),
+            ] {
+                let filter = filter.custom_tags(
+                    SingleLetterTag::lowercase(Alphabet::P),
+                    Vec::<String>::new(),
+                );
+                assert!(db.query(filter.clone()).await.unwrap().is_empty());
+                assert_eq!(db.count(filter.clone()).await.unwrap(), 0);
+                assert!(db.event_ids(filter).await.unwrap().is_empty());
+            }
+        }
+
+        #[tokio::test]
+        async fn test_deletion_property() {
+            use $crate::nostr::Filter;
+            use $crate::test_suite::generate_deletion_scenario;
+            use $crate::NostrEventsDatabase;
+
+            for seed in [1, 42, 1337, 0xdead_beef] {
+                let (events, expected) = generate_deletion_scenario(seed, 150);
+
+                let db = $new;
+
+                for event in events.iter() {
+                    db.save_event(event).await.unwrap();
+                }
+
+                let stored = db.query(Filter::new()).await.unwrap();
+                assert_eq!(stored.to_vec(), expected, "seed: {seed}");
+            }
+        }
+    };
+}
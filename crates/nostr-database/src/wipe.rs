@@ -12,4 +12,15 @@ use crate::error::DatabaseError;
 pub trait NostrDatabaseWipe {
     /// Wipe all data
     fn wipe(&self) -> BoxedFuture<Result<(), DatabaseError>>;
+
+    /// Delete all the events except the `keep_newest` ones
+    ///
+    /// In case of identical timestamps, the events with the lowest IDs are kept (NIP-01).
+    /// The removed events aren't marked as deleted, so they can be saved again.
+    ///
+    /// Return the number of removed events.
+    fn truncate_to(&self, keep_newest: usize) -> BoxedFuture<Result<usize, DatabaseError>> {
+        let _ = keep_newest;
+        Box::pin(async move { Err(DatabaseError::NotSupported) })
+    }
 }
@@ -24,6 +24,13 @@ pub enum IndexedDBError {
 
 impl std::error::Error for IndexedDBError {}
 
+impl IndexedDBError {
+    /// Check if the browser storage quota has been exceeded
+    pub fn is_quota_exceeded(&self) -> bool {
+        matches!(self, Self::DomException { name, .. } if name == "QuotaExceededError")
+    }
+}
+
 impl fmt::Display for IndexedDBError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -57,3 +64,29 @@ pub(crate) fn into_err(e: indexed_db_futures::web_sys::DomException) -> Database
     let indexed_err: IndexedDBError = e.into();
     DatabaseError::backend(indexed_err)
 }
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_is_quota_exceeded() {
+        let err = IndexedDBError::DomException {
+            code: 22,
+            name: String::from("QuotaExceededError"),
+            message: String::from("The quota has been exceeded."),
+        };
+        assert!(err.is_quota_exceeded());
+
+        let err = IndexedDBError::DomException {
+            code: 0,
+            name: String::from("ConstraintError"),
+            message: String::from("Key already exists in the object store."),
+        };
+        assert!(!err.is_quota_exceeded());
+
+        assert!(!IndexedDBError::MutexPoisoned.is_quota_exceeded());
+    }
+}
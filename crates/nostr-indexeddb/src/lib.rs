@@ -11,14 +11,16 @@
 #![cfg_attr(not(target_arch = "wasm32"), allow(unused))]
 #![allow(clippy::mutable_key_type)] // TODO: remove when possible. Needed to suppress false positive for `BTreeSet<Event>`
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt;
-use std::future::IntoFuture;
+use std::future::{Future, IntoFuture};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub extern crate nostr;
 pub extern crate nostr_database as database;
 
+use async_utility::time;
 use indexed_db_futures::js_sys::JsString;
 use indexed_db_futures::request::{IdbOpenDbRequestLike, OpenDbRequest};
 use indexed_db_futures::web_sys::IdbTransactionMode;
@@ -34,6 +36,8 @@ const CURRENT_DB_VERSION: u32 = 3;
 const EVENTS_CF: &str = "events";
 const EVENTS_SEEN_BY_RELAYS_CF: &str = "event-seen-by-relays";
 const ALL_STORES: [&str; 1] = [EVENTS_CF];
+/// Number of events decoded before yielding to the event loop
+const BULK_LOAD_CHUNK_SIZE: usize = 500;
 
 /// Helper struct for upgrading the inner DB.
 #[derive(Debug, Clone, Default)]
@@ -46,12 +50,52 @@ pub struct OngoingMigration {
     data: HashMap<&'static str, Vec<(JsValue, JsValue)>>,
 }
 
+/// Policy applied when the browser storage quota is exceeded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum EvictPolicy {
+    /// Return the error (default)
+    #[default]
+    Fail,
+    /// Evict the given number of oldest events and retry the save once
+    Oldest(usize),
+}
+
+/// IndexedDB database options
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct WebDatabaseOptions {
+    max_capacity: Option<usize>,
+    on_quota_exceeded: EvictPolicy,
+}
+
+impl WebDatabaseOptions {
+    /// New default options
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Max number of events to keep (default: unlimited)
+    #[inline]
+    pub fn max_capacity(mut self, max: usize) -> Self {
+        self.max_capacity = Some(max);
+        self
+    }
+
+    /// Policy applied when the browser storage quota is exceeded (default: [`EvictPolicy::Fail`])
+    #[inline]
+    pub fn on_quota_exceeded(mut self, policy: EvictPolicy) -> Self {
+        self.on_quota_exceeded = policy;
+        self
+    }
+}
+
 /// IndexedDB Nostr Database
 #[derive(Clone)]
 pub struct WebDatabase {
     db: Arc<IdbDatabase>,
     helper: DatabaseHelper,
     fbb: Arc<Mutex<FlatBufferBuilder<'static>>>,
+    opts: WebDatabaseOptions,
 }
 
 impl fmt::Debug for WebDatabase {
@@ -67,10 +111,15 @@ unsafe impl Send for WebDatabase {}
 unsafe impl Sync for WebDatabase {}
 
 impl WebDatabase {
-    async fn new<S>(name: S, helper: DatabaseHelper) -> Result<Self, DatabaseError>
+    async fn new<S>(name: S, opts: WebDatabaseOptions) -> Result<Self, DatabaseError>
     where
         S: AsRef<str>,
     {
+        let helper: DatabaseHelper = match opts.max_capacity {
+            Some(max) => DatabaseHelper::bounded(max),
+            None => DatabaseHelper::unbounded(),
+        };
+
         let mut this = Self {
             db: Arc::new(
                 IdbDatabase::open(name.as_ref())
@@ -81,6 +130,7 @@ impl WebDatabase {
             ),
             helper,
             fbb: Arc::new(Mutex::new(FlatBufferBuilder::with_capacity(70_000))),
+            opts,
         };
 
         this.migration().await?;
@@ -94,7 +144,7 @@ impl WebDatabase {
     where
         S: AsRef<str>,
     {
-        Self::new(name, DatabaseHelper::unbounded()).await
+        Self::new(name, WebDatabaseOptions::default()).await
     }
 
     /// Open database with **limited** capacity
@@ -102,7 +152,21 @@ impl WebDatabase {
     where
         S: AsRef<str>,
     {
-        Self::new(name, DatabaseHelper::bounded(max_capacity)).await
+        Self::new(name, WebDatabaseOptions::new().max_capacity(max_capacity)).await
+    }
+
+    /// Open database with [`WebDatabaseOptions`]
+    pub async fn open_with_opts<S>(name: S, opts: WebDatabaseOptions) -> Result<Self, DatabaseError>
+    where
+        S: AsRef<str>,
+    {
+        Self::new(name, opts).await
+    }
+
+    /// Approximate number of bytes used by the in-memory indexes
+    #[inline]
+    pub async fn index_memory_estimate(&self) -> usize {
+        self.helper.memory_estimate().await
     }
 
     async fn migration(&mut self) -> Result<(), IndexedDBError> {
@@ -201,26 +265,44 @@ impl WebDatabase {
     async fn bulk_load(&self) -> Result<(), IndexedDBError> {
         let tx = self
             .db
-            .transaction_on_one_with_mode(EVENTS_CF, IdbTransactionMode::Readwrite)?;
+            .transaction_on_one_with_mode(EVENTS_CF, IdbTransactionMode::Readonly)?;
         let store = tx.object_store(EVENTS_CF)?;
-        let events = store
-            .get_all()?
-            .await?
-            .into_iter()
-            .filter_map(js_value_to_string)
-            .filter_map(|v| {
-                let bytes = hex::decode(v).ok()?;
-                Event::decode(&bytes).ok()
-            })
-            .collect();
+        let values: Vec<JsValue> = store.get_all()?.await?.into_iter().collect();
+
+        // Decode events in chunks, yielding to the event loop to keep the UI responsive.
+        // The transaction auto-commits at the first yield, so it must not be reused.
+        let mut events: BTreeSet<Event> = BTreeSet::new();
+        for chunk in values.chunks(BULK_LOAD_CHUNK_SIZE) {
+            events.extend(
+                chunk
+                    .iter()
+                    .cloned()
+                    .filter_map(js_value_to_string)
+                    .filter_map(|v| {
+                        let bytes = hex::decode(v).ok()?;
+                        Event::decode(&bytes).ok()
+                    }),
+            );
+            time::sleep(Duration::ZERO).await;
+        }
 
         // Build indexes
+        // The events are sorted by the set, so the result doesn't depend on the decoding order.
         let to_discard: HashSet<EventId> = self.helper.bulk_load(events).await;
 
         // Discard events
-        for event_id in to_discard.into_iter() {
-            let key = JsValue::from(event_id.to_hex());
-            store.delete(&key)?.await?;
+        if !to_discard.is_empty() {
+            let tx = self
+                .db
+                .transaction_on_one_with_mode(EVENTS_CF, IdbTransactionMode::Readwrite)?;
+            let store = tx.object_store(EVENTS_CF)?;
+
+            for event_id in to_discard.into_iter() {
+                let key = JsValue::from(event_id.to_hex());
+                store.delete(&key)?;
+            }
+
+            tx.await.into_result()?;
         }
 
         Ok(())
@@ -228,15 +310,24 @@ impl WebDatabase {
 
     async fn _save_event(&self, event: &Event) -> Result<SaveEventStatus, IndexedDBError> {
         // Index event
-        let DatabaseEventResult { status, to_discard } = self.helper.index_event(event).await;
+        let res: DatabaseEventResult = self.helper.index_event(event).await;
+        self.store_indexed_event(event, res).await
+    }
 
-        if status.is_success() {
-            let tx = self
-                .db
-                .transaction_on_one_with_mode(EVENTS_CF, IdbTransactionMode::Readwrite)?;
-            let store = tx.object_store(EVENTS_CF)?;
-            let key = JsValue::from(event.id.to_hex());
+    async fn _replace_if_newer(&self, event: &Event) -> Result<SaveEventStatus, IndexedDBError> {
+        // Index event
+        let res: DatabaseEventResult = self.helper.replace_if_newer(event).await;
+        self.store_indexed_event(event, res).await
+    }
+
+    async fn store_indexed_event(
+        &self,
+        event: &Event,
+        res: DatabaseEventResult,
+    ) -> Result<SaveEventStatus, IndexedDBError> {
+        let DatabaseEventResult { status, to_discard } = res;
 
+        if status.is_success() {
             // Encode
             let event_hex: String = {
                 // Acquire FlatBuffers Builder
@@ -251,41 +342,151 @@ impl WebDatabase {
                 event
             };
 
-            // Store key-val
-            let value = JsValue::from(event_hex);
-            store.put_key_val(&key, &value)?;
+            let res: Result<(), IndexedDBError> = put_with_policy(
+                self.opts.on_quota_exceeded,
+                || self.put_event(&event.id, &event_hex, &to_discard),
+                |count| self.evict_oldest(count, &event.id),
+            )
+            .await;
+
+            // The event isn't stored: roll back the indexing
+            if let Err(e) = res {
+                self.rollback_indexed_event(event, &to_discard).await?;
+                return Err(e);
+            }
+        }
 
-            // Discard events no longer needed
-            for event_id in to_discard.into_iter() {
-                let key = JsValue::from(event_id.to_hex());
-                store.delete(&key)?;
+        Ok(status)
+    }
+
+    /// Remove the event from the indexes, forgetting the deletions it requested,
+    /// and index again the discarded events, still in the store
+    async fn rollback_indexed_event(
+        &self,
+        event: &Event,
+        to_discard: &HashSet<EventId>,
+    ) -> Result<(), IndexedDBError> {
+        self.helper.wipe_filter(Filter::new().id(event.id)).await;
+
+        if !to_discard.is_empty() {
+            let events: Vec<Event> = self.get_events(to_discard).await?;
+            self.helper.restore(events).await;
+        }
+
+        Ok(())
+    }
+
+    /// Get the stored events
+    async fn get_events(&self, ids: &HashSet<EventId>) -> Result<Vec<Event>, IndexedDBError> {
+        let tx = self
+            .db
+            .transaction_on_one_with_mode(EVENTS_CF, IdbTransactionMode::Readonly)?;
+        let store = tx.object_store(EVENTS_CF)?;
+
+        let mut events: Vec<Event> = Vec::with_capacity(ids.len());
+        for event_id in ids.iter() {
+            let key = JsValue::from(event_id.to_hex());
+            if let Some(value) = store.get(&key)?.await? {
+                if let Some(event) = js_value_to_string(value).and_then(|v| {
+                    let bytes = hex::decode(v).ok()?;
+                    Event::decode(&bytes).ok()
+                }) {
+                    events.push(event);
+                }
             }
+        }
 
-            tx.await.into_result()?;
+        Ok(events)
+    }
+
+    /// Store the encoded event and remove the discarded ones
+    async fn put_event(
+        &self,
+        event_id: &EventId,
+        event_hex: &str,
+        to_discard: &HashSet<EventId>,
+    ) -> Result<(), IndexedDBError> {
+        let tx = self
+            .db
+            .transaction_on_one_with_mode(EVENTS_CF, IdbTransactionMode::Readwrite)?;
+        let store = tx.object_store(EVENTS_CF)?;
+
+        // Store key-val
+        let key = JsValue::from(event_id.to_hex());
+        let value = JsValue::from(event_hex);
+        store.put_key_val(&key, &value)?;
+
+        // Discard events no longer needed
+        for event_id in to_discard.iter() {
+            let key = JsValue::from(event_id.to_hex());
+            store.delete(&key)?;
         }
 
-        Ok(status)
+        tx.await.into_result()?;
+
+        Ok(())
+    }
+
+    /// Remove the `count` oldest events, except the one being saved
+    async fn evict_oldest(&self, count: usize, keep: &EventId) -> Result<(), IndexedDBError> {
+        // Lookup ID: EVENT_ORD_IMPL
+        let ids: Vec<EventId> = self
+            .helper
+            .query_asc(Filter::new().limit(count.saturating_add(1)))
+            .await
+            .into_iter()
+            .map(|e| e.id)
+            .filter(|id| id != keep)
+            .take(count)
+            .collect();
+
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        // Remove from indexes
+        self.helper
+            .delete(Filter::new().ids(ids.iter().copied()))
+            .await;
+
+        // Remove from store
+        let tx = self
+            .db
+            .transaction_on_one_with_mode(EVENTS_CF, IdbTransactionMode::Readwrite)?;
+        let store = tx.object_store(EVENTS_CF)?;
+
+        for event_id in ids.iter() {
+            let key = JsValue::from(event_id.to_hex());
+            store.delete(&key)?;
+        }
+
+        tx.await.into_result()?;
+
+        Ok(())
     }
 
-    async fn _delete(&self, filter: Filter) -> Result<(), IndexedDBError> {
+    async fn _delete(&self, filter: Filter) -> Result<usize, IndexedDBError> {
         let tx = self
             .db
             .transaction_on_one_with_mode(EVENTS_CF, IdbTransactionMode::Readwrite)?;
         let store = tx.object_store(EVENTS_CF)?;
 
+        // Number of events before the deletion, needed if the whole store is cleared
+        let len: usize = self.helper.count(Filter::new()).await;
+
         match self.helper.delete(filter).await {
             Some(ids) => {
-                for id in ids.into_iter() {
+                for id in ids.iter() {
                     let key = JsValue::from(id.to_hex());
                     store.delete(&key)?.await?;
                 }
+                Ok(ids.len())
             }
             None => {
                 store.clear()?.await?;
+                Ok(len)
             }
-        };
-
-        Ok(())
+        }
     }
 
     async fn _wipe(&self) -> Result<(), IndexedDBError> {
@@ -307,6 +508,18 @@ impl NostrDatabase for WebDatabase {
     fn backend(&self) -> Backend {
         Backend::IndexedDB
     }
+
+    fn handled_kinds(&self) -> HandledKinds {
+        // Same as the in-memory helper
+        HandledKinds {
+            replaceable: true,
+            addressable: true,
+            ephemeral: true,
+            nip09: true,
+            nip40: false,
+            nip62: false,
+        }
+    }
 }
 
 impl NostrEventsDatabase for WebDatabase {
@@ -321,6 +534,17 @@ impl NostrEventsDatabase for WebDatabase {
         })
     }
 
+    fn replace_if_newer<'a>(
+        &'a self,
+        event: &'a Event,
+    ) -> BoxedFuture<'a, Result<SaveEventStatus, DatabaseError>> {
+        Box::pin(async move {
+            self._replace_if_newer(event)
+                .await
+                .map_err(DatabaseError::backend)
+        })
+    }
+
     fn check_id<'a>(
         &'a self,
         event_id: &'a EventId,
@@ -387,7 +611,7 @@ impl NostrEventsDatabase for WebDatabase {
         Box::pin(async move { Ok(self.helper.negentropy_items(filter).await) })
     }
 
-    fn delete(&self, filter: Filter) -> BoxedFuture<Result<(), DatabaseError>> {
+    fn delete(&self, filter: Filter) -> BoxedFuture<Result<usize, DatabaseError>> {
         Box::pin(async move { self._delete(filter).await.map_err(DatabaseError::backend) })
     }
 }
@@ -398,7 +622,150 @@ impl NostrDatabaseWipe for WebDatabase {
     }
 }
 
+/// Put, applying the [`EvictPolicy`] if the storage quota is exceeded
+async fn put_with_policy<P, PFut, E, EFut>(
+    policy: EvictPolicy,
+    put: P,
+    evict: E,
+) -> Result<(), IndexedDBError>
+where
+    P: Fn() -> PFut,
+    PFut: Future<Output = Result<(), IndexedDBError>>,
+    E: FnOnce(usize) -> EFut,
+    EFut: Future<Output = Result<(), IndexedDBError>>,
+{
+    match put().await {
+        Ok(()) => Ok(()),
+        Err(e) if e.is_quota_exceeded() => match policy {
+            EvictPolicy::Fail => Err(e),
+            EvictPolicy::Oldest(count) => {
+                evict(count).await?;
+
+                // Retry once
+                put().await
+            }
+        },
+        Err(e) => Err(e),
+    }
+}
+
 fn js_value_to_string(value: JsValue) -> Option<String> {
     let s: JsString = value.dyn_into().ok()?;
     Some(s.into())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_bulk_load_chunks() {
+        const NAME: &str = "test-bulk-load-chunks";
+
+        let db = WebDatabase::open(NAME).await.unwrap();
+        db.wipe().await.unwrap();
+
+        // Spans more than two chunks
+        let keys = Keys::generate();
+        let len: usize = BULK_LOAD_CHUNK_SIZE * 2 + 1;
+        for i in 0..len {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+        }
+
+        // Reopen, to load the events from the store
+        let db = WebDatabase::open(NAME).await.unwrap();
+        assert_eq!(db.count(Filter::new()).await.unwrap(), len);
+
+        db.wipe().await.unwrap();
+    }
+
+    fn quota_exceeded() -> IndexedDBError {
+        IndexedDBError::DomException {
+            code: 22,
+            name: String::from("QuotaExceededError"),
+            message: String::from("The quota has been exceeded."),
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_put_with_policy_evict_oldest() {
+        let puts = Cell::new(0);
+        let evicted = Cell::new(None);
+
+        // The first put exceeds the quota, the retry succeeds
+        put_with_policy(
+            EvictPolicy::Oldest(10),
+            || {
+                puts.set(puts.get() + 1);
+                let res = if puts.get() == 1 {
+                    Err(quota_exceeded())
+                } else {
+                    Ok(())
+                };
+                async move { res }
+            },
+            |count| {
+                evicted.set(Some(count));
+                async { Ok(()) }
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(puts.get(), 2);
+        assert_eq!(evicted.get(), Some(10));
+
+        // Retried only once
+        let puts = Cell::new(0);
+        let res = put_with_policy(
+            EvictPolicy::Oldest(10),
+            || {
+                puts.set(puts.get() + 1);
+                async { Err(quota_exceeded()) }
+            },
+            |_| async { Ok(()) },
+        )
+        .await;
+        assert!(res.unwrap_err().is_quota_exceeded());
+        assert_eq!(puts.get(), 2);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_put_with_policy_fail() {
+        let evicted = Cell::new(false);
+
+        let res = put_with_policy(
+            EvictPolicy::Fail,
+            || async { Err(quota_exceeded()) },
+            |_| {
+                evicted.set(true);
+                async { Ok(()) }
+            },
+        )
+        .await;
+        assert!(res.unwrap_err().is_quota_exceeded());
+        assert!(!evicted.get());
+
+        // Not a quota error: never evict
+        let res = put_with_policy(
+            EvictPolicy::Oldest(10),
+            || async { Err(IndexedDBError::MutexPoisoned) },
+            |_| {
+                evicted.set(true);
+                async { Ok(()) }
+            },
+        )
+        .await;
+        assert!(matches!(res, Err(IndexedDBError::MutexPoisoned)));
+        assert!(!evicted.get());
+    }
+}
@@ -10,18 +10,73 @@
 #![warn(rustdoc::bare_urls)]
 #![allow(clippy::mutable_key_type)]
 
+use std::collections::BTreeSet;
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
 use std::path::Path;
+use std::time::Duration;
 
+use async_utility::futures_util::stream::AbortHandle;
 use nostr_database::prelude::*;
 
 mod store;
 
 use self::store::Store;
 
+/// Point-in-time view of a [`NostrLMDB`]
+///
+/// Queries reflect the database state at the time the snapshot was taken,
+/// unaffected by the subsequent writes.
+///
+/// A snapshot holds a read transaction until dropped (including all its clones):
+/// it occupies one of the reader slots, and the pages freed by the subsequent writes can't be
+/// reused in the meantime, so the database file may grow. Avoid keeping it for a long time.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    inner: store::Snapshot,
+}
+
+impl Snapshot {
+    /// Get [`Event`] by [`EventId`]
+    pub fn event_by_id<'a>(
+        &'a self,
+        event_id: &'a EventId,
+    ) -> BoxedFuture<'a, Result<Option<Event>, DatabaseError>> {
+        Box::pin(async move {
+            self.inner
+                .get_event_by_id(event_id)
+                .await
+                .map_err(DatabaseError::from)
+        })
+    }
+
+    /// Count the number of events found with [`Filter`]
+    ///
+    /// Capped at the filter `limit`, if any.
+    pub fn count(&self, filter: Filter) -> BoxedFuture<Result<usize, DatabaseError>> {
+        Box::pin(async move { self.inner.count(filter).await.map_err(DatabaseError::from) })
+    }
+
+    /// Query the events
+    pub fn query(&self, filter: Filter) -> BoxedFuture<Result<Events, DatabaseError>> {
+        Box::pin(async move { self.inner.query(filter).await.map_err(DatabaseError::from) })
+    }
+}
+
 /// LMDB Nostr Database
 #[derive(Debug)]
 pub struct NostrLMDB {
     db: Store,
+    max_query_results: Option<usize>,
+    expiration_sweeper: Option<AbortHandle>,
+}
+
+impl Drop for NostrLMDB {
+    fn drop(&mut self) {
+        if let Some(sweeper) = &self.expiration_sweeper {
+            sweeper.abort();
+        }
+    }
 }
 
 impl NostrLMDB {
@@ -32,7 +87,129 @@ impl NostrLMDB {
         P: AsRef<Path>,
     {
         Ok(Self {
-            db: Store::open(path).map_err(DatabaseError::backend)?,
+            db: Store::open(path).map_err(DatabaseError::from)?,
+            max_query_results: None,
+            expiration_sweeper: None,
+        })
+    }
+
+    /// Set the max number of events returned by a single query (default: no limits)
+    ///
+    /// Applied when the filter doesn't set a smaller `limit`.
+    #[inline]
+    pub fn max_query_results(mut self, max: usize) -> Self {
+        self.max_query_results = Some(max);
+        self
+    }
+
+    /// Cache the results of the last `capacity` distinct queries (default: disabled)
+    ///
+    /// Useful when the same filters are queried repeatedly.
+    /// The cache is invalidated by the writes that may affect the results.
+    #[inline]
+    pub fn query_cache(mut self, capacity: NonZeroUsize) -> Self {
+        self.db.set_query_cache(capacity);
+        self
+    }
+
+    /// Periodically remove the expired events (NIP-40) in a background task (default: disabled)
+    ///
+    /// Replaces the previously started sweeper, if any. The task is stopped when the database is dropped.
+    /// Must be called within a runtime.
+    ///
+    /// Expired events are never returned by queries, even if not removed yet.
+    pub fn expiration_sweeper(mut self, interval: Duration) -> Self {
+        if let Some(sweeper) = self.expiration_sweeper.take() {
+            sweeper.abort();
+        }
+        self.expiration_sweeper = Some(self.db.spawn_expiration_sweeper(interval));
+        self
+    }
+
+    /// Walk the events matching the [`Filter`], newest first, without collecting them in memory
+    ///
+    /// Return [`ControlFlow::Break`] from the callback to stop early.
+    ///
+    /// The database is walked on a blocking task, in a single read transaction,
+    /// and the events are passed to the callback as they are read, with a small bounded buffer.
+    /// The filters with no indexed fields are walked by scanning the events in the `since`-`until` range,
+    /// so this is suited for processing big amounts of events.
+    ///
+    /// Use [`NostrLMDB::for_each_blocking`] to borrow the events from the database, without copying them.
+    pub fn for_each<'a, F>(
+        &'a self,
+        filter: Filter,
+        f: F,
+    ) -> BoxedFuture<'a, Result<(), DatabaseError>>
+    where
+        F: FnMut(&Event) -> ControlFlow<()> + Send + 'a,
+    {
+        Box::pin(async move {
+            self.db
+                .for_each(filter, f)
+                .await
+                .map_err(DatabaseError::from)
+        })
+    }
+
+    /// Walk the events matching the [`Filter`], newest first, borrowing them from the database
+    ///
+    /// Like [`NostrLMDB::for_each`], but the events are not copied unless needed.
+    ///
+    /// **This blocks the current thread** until the walk ends:
+    /// in async code, call it from a blocking task (i.e., `spawn_blocking`).
+    pub fn for_each_blocking<F>(&self, filter: Filter, f: F) -> Result<(), DatabaseError>
+    where
+        F: FnMut(&EventBorrow) -> ControlFlow<()>,
+    {
+        self.db
+            .for_each_blocking(filter, f)
+            .map_err(DatabaseError::from)
+    }
+
+    /// Get the events by IDs, using a single read transaction
+    ///
+    /// The results are positional: `None` for the missing or deleted events.
+    pub fn event_by_ids(
+        &self,
+        ids: &[EventId],
+    ) -> BoxedFuture<Result<Vec<Option<Event>>, DatabaseError>> {
+        let ids: Vec<EventId> = ids.to_vec();
+        Box::pin(async move {
+            self.db
+                .get_events_by_ids(ids)
+                .await
+                .map_err(DatabaseError::from)
+        })
+    }
+
+    /// Get the events saved after the `seq` insertion sequence number, oldest first
+    ///
+    /// The sequence numbers are assigned at insertion time and are independent of `created_at`,
+    /// so the database can be tailed by passing the returned sequence number to the next call.
+    /// Start from `0` to get all the events.
+    ///
+    /// Return at most `limit` events and the new sequence number.
+    pub fn events_since_seq(
+        &self,
+        seq: u64,
+        limit: usize,
+    ) -> BoxedFuture<Result<(Vec<Event>, u64), DatabaseError>> {
+        Box::pin(async move {
+            self.db
+                .events_since_seq(seq, limit)
+                .await
+                .map_err(DatabaseError::from)
+        })
+    }
+
+    /// Take a point-in-time [`Snapshot`] of the database
+    ///
+    /// See [`Snapshot`] for the cost of keeping it.
+    pub fn snapshot(&self) -> BoxedFuture<Result<Snapshot, DatabaseError>> {
+        Box::pin(async move {
+            let inner: store::Snapshot = self.db.snapshot().await.map_err(DatabaseError::from)?;
+            Ok(Snapshot { inner })
         })
     }
 }
@@ -42,18 +219,40 @@ impl NostrDatabase for NostrLMDB {
     fn backend(&self) -> Backend {
         Backend::LMDB
     }
+
+    fn handled_kinds(&self) -> HandledKinds {
+        HandledKinds {
+            replaceable: true,
+            addressable: true,
+            ephemeral: true,
+            nip09: true,
+            nip40: true,
+            nip62: false,
+        }
+    }
+
+    fn flush(&self) -> BoxedFuture<Result<(), DatabaseError>> {
+        Box::pin(async move { self.db.flush().await.map_err(DatabaseError::from) })
+    }
 }
 
 impl NostrEventsDatabase for NostrLMDB {
     fn save_event<'a>(
         &'a self,
         event: &'a Event,
+    ) -> BoxedFuture<'a, Result<SaveEventStatus, DatabaseError>> {
+        Box::pin(async move { self.db.save_event(event).await.map_err(DatabaseError::from) })
+    }
+
+    fn replace_if_newer<'a>(
+        &'a self,
+        event: &'a Event,
     ) -> BoxedFuture<'a, Result<SaveEventStatus, DatabaseError>> {
         Box::pin(async move {
             self.db
-                .save_event(event)
+                .replace_if_newer(event)
                 .await
-                .map_err(DatabaseError::backend)
+                .map_err(DatabaseError::from)
         })
     }
 
@@ -66,14 +265,14 @@ impl NostrEventsDatabase for NostrLMDB {
                 .db
                 .event_is_deleted(*event_id)
                 .await
-                .map_err(DatabaseError::backend)?
+                .map_err(DatabaseError::from)?
             {
                 Ok(DatabaseEventStatus::Deleted)
             } else if self
                 .db
                 .has_event(event_id)
                 .await
-                .map_err(DatabaseError::backend)?
+                .map_err(DatabaseError::from)?
             {
                 Ok(DatabaseEventStatus::Saved)
             } else {
@@ -82,6 +281,19 @@ impl NostrEventsDatabase for NostrLMDB {
         })
     }
 
+    fn has_event<'a>(
+        &'a self,
+        event_id: &'a EventId,
+    ) -> BoxedFuture<'a, Result<bool, DatabaseError>> {
+        Box::pin(async move {
+            // Deleted events are removed from the store
+            self.db
+                .has_event(event_id)
+                .await
+                .map_err(DatabaseError::from)
+        })
+    }
+
     fn has_coordinate_been_deleted<'a>(
         &'a self,
         coordinate: &'a CoordinateBorrow<'a>,
@@ -92,7 +304,7 @@ impl NostrEventsDatabase for NostrLMDB {
                 .db
                 .when_is_coordinate_deleted(coordinate)
                 .await
-                .map_err(DatabaseError::backend)?
+                .map_err(DatabaseError::from)?
             {
                 Ok(&t >= timestamp)
             } else {
@@ -109,16 +321,58 @@ impl NostrEventsDatabase for NostrLMDB {
             self.db
                 .get_event_by_id(event_id)
                 .await
-                .map_err(DatabaseError::backend)
+                .map_err(DatabaseError::from)
         })
     }
 
     fn count(&self, filter: Filter) -> BoxedFuture<Result<usize, DatabaseError>> {
-        Box::pin(async move { self.db.count(filter).await.map_err(DatabaseError::backend) })
+        Box::pin(async move { self.db.count(filter).await.map_err(DatabaseError::from) })
+    }
+
+    fn count_by_filters(&self, filters: Vec<Filter>) -> BoxedFuture<Result<usize, DatabaseError>> {
+        Box::pin(async move {
+            self.db
+                .count_by_filters(filters)
+                .await
+                .map_err(DatabaseError::from)
+        })
     }
 
     fn query(&self, filter: Filter) -> BoxedFuture<Result<Events, DatabaseError>> {
-        Box::pin(async move { self.db.query(filter).await.map_err(DatabaseError::backend) })
+        Box::pin(async move {
+            let filter: Filter = cap_filter_limit(filter, self.max_query_results);
+            self.db.query(filter).await.map_err(DatabaseError::from)
+        })
+    }
+
+    fn query_page(
+        &self,
+        filter: Filter,
+        cursor: Option<PageCursor>,
+        limit: usize,
+    ) -> BoxedFuture<Result<(Events, Option<PageCursor>), DatabaseError>> {
+        // Cap the page, not the internal queries, so the pages have no gaps
+        let limit: usize = self.max_query_results.map_or(limit, |max| limit.min(max));
+        Box::pin(query_page_with(
+            move |filter| async move { self.db.query(filter).await.map_err(DatabaseError::from) },
+            filter,
+            cursor,
+            limit,
+        ))
+    }
+
+    fn latest(
+        &self,
+        filter: Filter,
+    ) -> BoxedFuture<Result<Option<(EventId, Timestamp)>, DatabaseError>> {
+        Box::pin(async move { self.db.latest(filter).await.map_err(DatabaseError::from) })
+    }
+
+    fn event_ids(&self, filter: Filter) -> BoxedFuture<Result<BTreeSet<EventId>, DatabaseError>> {
+        Box::pin(async move {
+            let filter: Filter = cap_filter_limit(filter, self.max_query_results);
+            self.db.event_ids(filter).await.map_err(DatabaseError::from)
+        })
     }
 
     fn negentropy_items(
@@ -129,25 +383,40 @@ impl NostrEventsDatabase for NostrLMDB {
             self.db
                 .negentropy_items(filter)
                 .await
-                .map_err(DatabaseError::backend)
+                .map_err(DatabaseError::from)
         })
     }
 
-    fn delete(&self, filter: Filter) -> BoxedFuture<Result<(), DatabaseError>> {
-        Box::pin(async move { self.db.delete(filter).await.map_err(DatabaseError::backend) })
+    fn delete(&self, filter: Filter) -> BoxedFuture<Result<usize, DatabaseError>> {
+        Box::pin(async move { self.db.delete(filter).await.map_err(DatabaseError::from) })
+    }
+
+    fn prune(&self) -> BoxedFuture<Result<PruneReport, DatabaseError>> {
+        Box::pin(async move { self.db.prune().await.map_err(DatabaseError::from) })
     }
 }
 
 impl NostrDatabaseWipe for NostrLMDB {
     #[inline]
     fn wipe(&self) -> BoxedFuture<Result<(), DatabaseError>> {
-        Box::pin(async move { self.db.wipe().await.map_err(DatabaseError::backend) })
+        Box::pin(async move { self.db.wipe().await.map_err(DatabaseError::from) })
+    }
+
+    fn truncate_to(&self, keep_newest: usize) -> BoxedFuture<Result<usize, DatabaseError>> {
+        Box::pin(async move {
+            self.db
+                .truncate_to(keep_newest)
+                .await
+                .map_err(DatabaseError::from)
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::ops::Deref;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
     use std::time::Duration;
 
     use tempfile::TempDir;
@@ -256,6 +525,8 @@ mod tests {
         }
     }
 
+    nostr_database::database_unit_tests!(TempDatabase::new());
+
     #[tokio::test]
     async fn test_event_by_id() {
         let db = TempDatabase::new();
@@ -471,4 +742,726 @@ mod tests {
 
         assert_eq!(db.count_all().await, 2);
     }
+
+    #[tokio::test]
+    async fn test_max_query_results() {
+        let path = tempfile::tempdir().unwrap();
+        let db = NostrLMDB::open(&path).unwrap().max_query_results(3);
+        let keys = Keys::generate();
+        let base = Timestamp::from_secs(1_700_000_000);
+
+        let mut events: Vec<Event> = Vec::new();
+        for i in 0..10 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .custom_created_at(base + i)
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+            events.push(event);
+        }
+
+        // Newest first
+        let expected: Vec<Event> = events.into_iter().rev().take(3).collect();
+
+        let stored = db.query(Filter::new().kind(Kind::TextNote)).await.unwrap();
+        assert_eq!(stored.to_vec(), expected);
+
+        // A bigger limit is capped too
+        let filter = Filter::new().kind(Kind::TextNote).limit(100);
+        assert_eq!(db.query(filter).await.unwrap().len(), 3);
+
+        // A smaller limit is kept
+        let filter = Filter::new().kind(Kind::TextNote).limit(2);
+        assert_eq!(db.query(filter).await.unwrap().len(), 2);
+
+        // Applied to the IDs too
+        let ids = db
+            .event_ids(Filter::new().kind(Kind::TextNote))
+            .await
+            .unwrap();
+        let expected: BTreeSet<EventId> = expected.iter().map(|e| e.id).collect();
+        assert_eq!(ids, expected);
+    }
+
+    #[tokio::test]
+    async fn test_for_each() {
+        let db = TempDatabase::new();
+
+        db.add_random_events().await;
+        for i in 0..20 {
+            db.add_event(EventBuilder::text_note("a".repeat(i))).await;
+        }
+
+        let filter = Filter::new().kind(Kind::TextNote);
+
+        let expected: usize = db
+            .query(filter.clone())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.content.len())
+            .sum();
+
+        let mut sum: usize = 0;
+        db.for_each(filter.clone(), |event| {
+            sum += event.content.len();
+            ControlFlow::Continue(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(sum, expected);
+
+        // Stop early
+        let mut count: usize = 0;
+        db.for_each(filter.clone(), |_| {
+            count += 1;
+            match count {
+                5 => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(count, 5);
+
+        // Blocking, borrowing the events
+        let mut sum: usize = 0;
+        db.for_each_blocking(filter, |event| {
+            sum += event.content.len();
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert_eq!(sum, expected);
+
+        // Indexed filter
+        let keys = Keys::generate();
+        let event = EventBuilder::text_note("Indexed")
+            .sign_with_keys(&keys)
+            .unwrap();
+        db.save_event(&event).await.unwrap();
+
+        let mut ids: Vec<EventId> = Vec::new();
+        db.for_each(Filter::new().author(keys.public_key), |event| {
+            ids.push(event.id);
+            ControlFlow::Continue(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(ids, vec![event.id]);
+    }
+
+    #[tokio::test]
+    async fn test_delete_count() {
+        let db = TempDatabase::new();
+        let keys = Keys::generate();
+
+        for i in 0..7 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+        }
+        let metadata = EventBuilder::metadata(&Metadata::new().name("name"))
+            .sign_with_keys(&keys)
+            .unwrap();
+        db.save_event(&metadata).await.unwrap();
+
+        let filter = Filter::new().kind(Kind::TextNote);
+        let existing: usize = db.count(filter.clone()).await.unwrap();
+        assert_eq!(existing, 7);
+
+        assert_eq!(db.delete(filter.clone()).await.unwrap(), existing);
+        assert_eq!(db.count(filter.clone()).await.unwrap(), 0);
+
+        // Nothing left to delete
+        assert_eq!(db.delete(filter).await.unwrap(), 0);
+
+        // Delete everything
+        assert_eq!(db.delete(Filter::new()).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_has_event() {
+        let db = TempDatabase::new();
+        let keys = Keys::generate();
+
+        let existing = EventBuilder::text_note("Existing")
+            .sign_with_keys(&keys)
+            .unwrap();
+        let deleted = EventBuilder::text_note("Deleted")
+            .sign_with_keys(&keys)
+            .unwrap();
+        let absent = EventBuilder::text_note("Absent")
+            .sign_with_keys(&keys)
+            .unwrap();
+        let deletion = EventBuilder::delete([deleted.id])
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        db.save_event(&existing).await.unwrap();
+        db.save_event(&deleted).await.unwrap();
+        db.save_event(&deletion).await.unwrap();
+
+        assert!(db.has_event(&existing.id).await.unwrap());
+        assert!(!db.has_event(&absent.id).await.unwrap());
+
+        assert_eq!(
+            db.check_id(&deleted.id).await.unwrap(),
+            DatabaseEventStatus::Deleted
+        );
+        assert!(!db.has_event(&deleted.id).await.unwrap());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_expiration_sweep() {
+        let path = tempfile::tempdir().unwrap();
+        let db = NostrLMDB::open(&path)
+            .unwrap()
+            .expiration_sweeper(Duration::from_millis(50));
+
+        let keys = Keys::generate();
+        let expired = EventBuilder::text_note("Expired")
+            .tag(Tag::expiration(Timestamp::from_secs(1_600_000_000)))
+            .sign_with_keys(&keys)
+            .unwrap();
+        let valid = EventBuilder::text_note("Valid")
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        db.save_event(&expired).await.unwrap();
+        db.save_event(&valid).await.unwrap();
+
+        // Never returned, even before the sweep
+        let filter = Filter::new().author(keys.public_key);
+        let events = db.query(filter.clone()).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events.first().unwrap().id, valid.id);
+        assert_eq!(db.count(filter).await.unwrap(), 1);
+        assert!(db.event_by_id(&valid.id).await.unwrap().is_some());
+
+        // Eventually removed
+        let mut removed: bool = false;
+        for _ in 0..100 {
+            if !db.has_event(&expired.id).await.unwrap() {
+                removed = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(removed);
+        assert!(db.has_event(&valid.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_truncate_to() {
+        let db = TempDatabase::new();
+        let keys = Keys::generate();
+        let base = Timestamp::from_secs(1_700_000_000);
+
+        let mut events: Vec<Event> = Vec::new();
+        for i in 0..10 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .custom_created_at(base + i)
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+            events.push(event);
+        }
+
+        assert_eq!(db.truncate_to(3).await.unwrap(), 7);
+
+        // The 3 newest remain
+        let stored = db.query(Filter::new()).await.unwrap();
+        let ids: Vec<EventId> = stored.into_iter().map(|e| e.id).collect();
+        let expected: Vec<EventId> = events.iter().rev().take(3).map(|e| e.id).collect();
+        assert_eq!(ids, expected);
+
+        // Nothing else to remove
+        assert_eq!(db.truncate_to(3).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_query_cache() {
+        let path = tempfile::tempdir().unwrap();
+        let db = NostrLMDB::open(&path)
+            .unwrap()
+            .query_cache(NonZeroUsize::new(2).unwrap());
+
+        let keys_a = Keys::generate();
+        let keys_b = Keys::generate();
+
+        for i in 0..5 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .sign_with_keys(&keys_a)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+        }
+
+        let filter = Filter::new().author(keys_a.public_key).kind(Kind::TextNote);
+
+        // Cached repeated query
+        let first = db.query(filter.clone()).await.unwrap();
+        let second = db.query(filter.clone()).await.unwrap();
+        assert_eq!(first.len(), 5);
+        assert_eq!(first, second);
+
+        // Irrelevant write
+        let other = EventBuilder::text_note("Other")
+            .sign_with_keys(&keys_b)
+            .unwrap();
+        db.save_event(&other).await.unwrap();
+        assert_eq!(db.query(filter.clone()).await.unwrap(), first);
+
+        // Relevant write invalidates the cache
+        let new = EventBuilder::text_note("New")
+            .sign_with_keys(&keys_a)
+            .unwrap();
+        db.save_event(&new).await.unwrap();
+        let events = db.query(filter.clone()).await.unwrap();
+        assert_eq!(events.len(), 6);
+        assert!(events.contains(&new));
+
+        // Write of another author matching a cached tag query invalidates the cache
+        let tag_filter = Filter::new().pubkey(keys_a.public_key);
+        assert!(db.query(tag_filter.clone()).await.unwrap().is_empty());
+        let mention = EventBuilder::text_note("Mention")
+            .tag(Tag::public_key(keys_a.public_key))
+            .sign_with_keys(&keys_b)
+            .unwrap();
+        db.save_event(&mention).await.unwrap();
+        assert_eq!(
+            db.query(tag_filter).await.unwrap().to_vec(),
+            vec![mention.clone()]
+        );
+
+        // Delete invalidates the cache
+        db.delete(Filter::new().id(new.id)).await.unwrap();
+        assert_eq!(db.query(filter.clone()).await.unwrap(), first);
+
+        // Deletion request invalidates the cache
+        let target = first.first().unwrap().id;
+        let deletion = EventBuilder::delete([target])
+            .sign_with_keys(&keys_a)
+            .unwrap();
+        db.save_event(&deletion).await.unwrap();
+        let events = db.query(filter).await.unwrap();
+        assert_eq!(events.len(), 4);
+        assert!(events.iter().all(|e| e.id != target));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_query_cache_expiration_sweep() {
+        let path = tempfile::tempdir().unwrap();
+        let db = NostrLMDB::open(&path)
+            .unwrap()
+            .query_cache(NonZeroUsize::new(2).unwrap())
+            .expiration_sweeper(Duration::from_millis(50));
+
+        let keys = Keys::generate();
+        let now = Timestamp::now();
+        let older = EventBuilder::text_note("Older")
+            .custom_created_at(now - 10)
+            .sign_with_keys(&keys)
+            .unwrap();
+        let expiring = EventBuilder::text_note("Expiring")
+            .tag(Tag::expiration(now + 1))
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        db.save_event(&older).await.unwrap();
+        db.save_event(&expiring).await.unwrap();
+
+        // Cache the newest event
+        let filter = Filter::new().author(keys.public_key).limit(1);
+        let events = db.query(filter.clone()).await.unwrap();
+        assert_eq!(events.first().unwrap().id, expiring.id);
+
+        let mut removed: bool = false;
+        for _ in 0..200 {
+            if !db.has_event(&expiring.id).await.unwrap() {
+                removed = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(removed);
+
+        // The sweep invalidated the cached results
+        let events = db.query(filter).await.unwrap();
+        assert_eq!(events.to_vec(), vec![older]);
+    }
+
+    #[tokio::test]
+    async fn test_flush() {
+        let db = TempDatabase::new();
+        let keys = Keys::generate();
+
+        let event = EventBuilder::text_note("Flushed")
+            .sign_with_keys(&keys)
+            .unwrap();
+        db.save_event(&event).await.unwrap();
+
+        db.flush().await.unwrap();
+
+        let events = db.query(Filter::new().id(event.id)).await.unwrap();
+        assert_eq!(events.to_vec(), vec![event]);
+    }
+
+    #[tokio::test]
+    async fn test_events_since_seq() {
+        let db = TempDatabase::new();
+        let keys = Keys::generate();
+
+        // The second batch has older timestamps
+        let first: Vec<Event> = (0..3)
+            .map(|i| {
+                EventBuilder::text_note(format!("First {i}"))
+                    .custom_created_at(Timestamp::from_secs(2_000 + i))
+                    .sign_with_keys(&keys)
+                    .unwrap()
+            })
+            .collect();
+        let second: Vec<Event> = (0..2)
+            .map(|i| {
+                EventBuilder::text_note(format!("Second {i}"))
+                    .custom_created_at(Timestamp::from_secs(1_000 + i))
+                    .sign_with_keys(&keys)
+                    .unwrap()
+            })
+            .collect();
+
+        for event in first.iter() {
+            db.save_event(event).await.unwrap();
+        }
+
+        let (events, seq) = db.events_since_seq(0, 100).await.unwrap();
+        assert_eq!(events, first);
+
+        for event in second.iter() {
+            db.save_event(event).await.unwrap();
+        }
+
+        let (events, new_seq) = db.events_since_seq(seq, 100).await.unwrap();
+        assert_eq!(events, second);
+        assert!(new_seq > seq);
+
+        // Nothing new
+        let (events, last_seq) = db.events_since_seq(new_seq, 100).await.unwrap();
+        assert!(events.is_empty());
+        assert_eq!(last_seq, new_seq);
+
+        // Limit
+        let (events, _) = db.events_since_seq(0, 2).await.unwrap();
+        assert_eq!(events, first[..2]);
+    }
+
+    #[tokio::test]
+    async fn test_events_since_seq_after_removal() {
+        let db = TempDatabase::new();
+        let keys = Keys::generate();
+
+        let events: Vec<Event> = (0..3)
+            .map(|i| {
+                EventBuilder::text_note(format!("Note {i}"))
+                    .sign_with_keys(&keys)
+                    .unwrap()
+            })
+            .collect();
+
+        for event in events.iter() {
+            db.save_event(event).await.unwrap();
+        }
+
+        // The removed events don't consume the limit
+        db.delete(Filter::new().ids([events[0].id, events[1].id]))
+            .await
+            .unwrap();
+        let (found, seq) = db.events_since_seq(0, 1).await.unwrap();
+        assert_eq!(found, events[2..]);
+
+        // The sequence numbers aren't reused after a wipe
+        db.wipe().await.unwrap();
+        db.save_event(&events[0]).await.unwrap();
+
+        let (found, new_seq) = db.events_since_seq(seq, 10).await.unwrap();
+        assert_eq!(found, events[..1]);
+        assert!(new_seq > seq);
+    }
+
+    #[tokio::test]
+    async fn test_event_by_ids() {
+        let db = TempDatabase::new();
+        let keys = Keys::generate();
+
+        let first = EventBuilder::text_note("First")
+            .sign_with_keys(&keys)
+            .unwrap();
+        let second = EventBuilder::text_note("Second")
+            .sign_with_keys(&keys)
+            .unwrap();
+        let missing = EventBuilder::text_note("Missing")
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        db.save_event(&first).await.unwrap();
+        db.save_event(&second).await.unwrap();
+
+        let events = db
+            .event_by_ids(&[second.id, missing.id, first.id])
+            .await
+            .unwrap();
+        assert_eq!(events, vec![Some(second), None, Some(first)]);
+    }
+
+    #[tokio::test]
+    async fn test_wipe_during_read() {
+        let db = Arc::new(TempDatabase::new());
+
+        for i in 0..10 {
+            db.add_event(EventBuilder::text_note(format!("Note {i}")))
+                .await;
+        }
+
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel::<()>();
+        let (wiped_tx, wiped_rx) = std::sync::mpsc::channel::<()>();
+
+        // Pause the read after the first event, until the wipe is committed
+        let mut started_tx = Some(started_tx);
+        let visited = Arc::new(AtomicUsize::new(0));
+        let v = visited.clone();
+        let reader = db.clone();
+        let read = tokio::task::spawn_blocking(move || {
+            reader.for_each_blocking(Filter::new(), |_| {
+                if let Some(tx) = started_tx.take() {
+                    tx.send(()).unwrap();
+                    wiped_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+                }
+                v.fetch_add(1, Ordering::SeqCst);
+                ControlFlow::Continue(())
+            })
+        });
+
+        started_rx.await.unwrap();
+        db.wipe().await.unwrap();
+        wiped_tx.send(()).unwrap();
+
+        read.await.unwrap().unwrap();
+
+        // The read saw the pre-wipe snapshot
+        assert_eq!(visited.load(Ordering::SeqCst), 10);
+
+        // The new reads see the empty database
+        assert_eq!(db.count(Filter::new()).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_query_page() {
+        let db = TempDatabase::new();
+        let keys = Keys::generate();
+
+        // Pairs of events share the same timestamp
+        for i in 0..10 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .custom_created_at(Timestamp::from_secs(1000 + i / 2))
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+        }
+
+        let mut ids: Vec<EventId> = Vec::new();
+        let mut cursor: Option<PageCursor> = None;
+        loop {
+            let (events, next) = db.query_page(Filter::new(), cursor, 3).await.unwrap();
+            ids.extend(events.into_iter().map(|e| e.id));
+
+            match next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        let expected: Vec<EventId> = db
+            .query(Filter::new())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.id)
+            .collect();
+        assert_eq!(expected.len(), 10);
+        assert_eq!(ids, expected);
+    }
+
+    #[tokio::test]
+    async fn test_query_page_max_query_results() {
+        let path = tempfile::tempdir().unwrap();
+        let db = NostrLMDB::open(&path).unwrap().max_query_results(2);
+        let keys = Keys::generate();
+
+        // Pairs of events share the same timestamp
+        let mut events: Vec<Event> = Vec::new();
+        for i in 0..10 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .custom_created_at(Timestamp::from_secs(1000 + i / 2))
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+            events.push(event);
+        }
+        events.sort();
+
+        // The page size is above the cap
+        let mut ids: Vec<EventId> = Vec::new();
+        let mut cursor: Option<PageCursor> = None;
+        loop {
+            let (page, next) = db.query_page(Filter::new(), cursor, 3).await.unwrap();
+            assert!(page.len() <= 2);
+            ids.extend(page.into_iter().map(|e| e.id));
+
+            match next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        let expected: Vec<EventId> = events.into_iter().map(|e| e.id).collect();
+        assert_eq!(ids, expected);
+    }
+
+    #[tokio::test]
+    async fn test_replace_if_newer() {
+        let db = TempDatabase::new();
+        let keys = Keys::generate();
+
+        let metadata = |name: &str, secs: u64| {
+            EventBuilder::metadata(&Metadata::new().name(name))
+                .custom_created_at(Timestamp::from_secs(secs))
+                .sign_with_keys(&keys)
+                .unwrap()
+        };
+
+        let stored = metadata("stored", 2000);
+        assert!(db.replace_if_newer(&stored).await.unwrap().is_success());
+
+        // Older and same timestamp are rejected, regardless of the ID
+        for event in [metadata("older", 1000), metadata("tie", 2000)] {
+            assert_eq!(
+                db.replace_if_newer(&event).await.unwrap(),
+                SaveEventStatus::Rejected(RejectedReason::Replaced)
+            );
+        }
+
+        let newer = metadata("newer", 3000);
+        assert!(db.replace_if_newer(&newer).await.unwrap().is_success());
+
+        let events = db
+            .query(Filter::new().author(keys.public_key).kind(Kind::Metadata))
+            .await
+            .unwrap();
+        assert_eq!(events.to_vec(), vec![newer]);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let db = TempDatabase::new();
+
+        for i in 0..5 {
+            db.add_event(EventBuilder::text_note(format!("Note {i}")))
+                .await;
+        }
+
+        let snapshot = db.snapshot().await.unwrap();
+
+        let (_, event) = db
+            .add_event(EventBuilder::text_note("After snapshot"))
+            .await;
+
+        assert_eq!(db.count(Filter::new()).await.unwrap(), 6);
+        assert_eq!(snapshot.count(Filter::new()).await.unwrap(), 5);
+        assert_eq!(snapshot.query(Filter::new()).await.unwrap().len(), 5);
+        assert!(snapshot.event_by_id(&event.id).await.unwrap().is_none());
+        assert_eq!(
+            db.event_by_id(&event.id).await.unwrap().map(|e| e.id),
+            Some(event.id)
+        );
+    }
+
+    #[test]
+    fn test_backend_is_persistent() {
+        let db = TempDatabase::new();
+        assert_eq!(db.backend(), Backend::LMDB);
+        assert!(db.backend().is_persistent());
+    }
+
+    #[tokio::test]
+    async fn test_count_with_limit() {
+        let db = TempDatabase::new();
+
+        let keys = Keys::generate();
+        for i in 0..10 {
+            let event = EventBuilder::text_note(format!("Note {i}"))
+                .sign_with_keys(&keys)
+                .unwrap();
+            db.save_event(&event).await.unwrap();
+        }
+
+        let filter = Filter::new().author(keys.public_key).kind(Kind::TextNote);
+        assert_eq!(db.count(filter.clone()).await.unwrap(), 10);
+        assert_eq!(db.count(filter.clone().limit(3)).await.unwrap(), 3);
+        assert_eq!(db.count(filter.limit(20)).await.unwrap(), 10);
+        assert_eq!(db.count(Filter::new().limit(3)).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_event_ids() {
+        let db = TempDatabase::new();
+
+        let keys_a = Keys::generate();
+        let keys_b = Keys::generate();
+        let base = Timestamp::from_secs(1_700_000_000);
+
+        // Interleaved authors, with pairs of events sharing the same timestamp
+        for i in 0..10 {
+            for keys in [&keys_a, &keys_b] {
+                let event = EventBuilder::text_note(format!("Note {i}"))
+                    .custom_created_at(base + i / 2)
+                    .sign_with_keys(keys)
+                    .unwrap();
+                db.save_event(&event).await.unwrap();
+            }
+        }
+
+        // Expired, but not removed yet
+        let expired = EventBuilder::text_note("Expired")
+            .custom_created_at(base + 100)
+            .tag(Tag::expiration(Timestamp::from_secs(1_600_000_000)))
+            .sign_with_keys(&keys_a)
+            .unwrap();
+        db.save_event(&expired).await.unwrap();
+
+        for filter in [
+            Filter::new().author(keys_a.public_key),
+            Filter::new().author(keys_a.public_key).limit(3),
+            Filter::new()
+                .authors([keys_a.public_key, keys_b.public_key])
+                .limit(5),
+            Filter::new()
+                .authors([keys_a.public_key, keys_b.public_key])
+                .kind(Kind::TextNote)
+                .limit(7),
+            Filter::new().kind(Kind::TextNote).limit(3),
+            Filter::new().limit(4),
+            Filter::new().since(base + 2).until(base + 3),
+            Filter::new().kind(Kind::Metadata),
+            Filter::new().id(expired.id),
+        ] {
+            let expected: BTreeSet<EventId> = db
+                .query(filter.clone())
+                .await
+                .unwrap()
+                .iter()
+                .map(|e| e.id)
+                .collect();
+            assert_eq!(db.event_ids(filter).await.unwrap(), expected);
+        }
+    }
 }
@@ -0,0 +1,168 @@
+// Copyright (c) 2022-2023 Yuki Kishimoto
+// Copyright (c) 2023-2025 Rust Nostr Developers
+// Distributed under the MIT software license
+
+//! Query cache
+
+use std::collections::{BTreeMap, HashMap};
+use std::num::NonZeroUsize;
+
+use nostr::{Event, EventId, Filter, Kind};
+
+#[derive(Debug)]
+struct Entry {
+    ids: Vec<EventId>,
+    /// Tick of the last use
+    last_used: u64,
+}
+
+/// LRU cache of the event IDs returned by the queries
+#[derive(Debug)]
+pub(crate) struct QueryCache {
+    capacity: NonZeroUsize,
+    entries: HashMap<Filter, Entry>,
+    /// Filters by last use, from the least to the most recently used
+    order: BTreeMap<u64, Filter>,
+    /// Incremented at every use
+    tick: u64,
+    /// Incremented at every invalidation
+    generation: u64,
+}
+
+impl QueryCache {
+    pub(crate) fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::with_capacity(capacity.get()),
+            order: BTreeMap::new(),
+            tick: 0,
+            generation: 0,
+        }
+    }
+
+    #[inline]
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    #[inline]
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Get the cached IDs, marking the filter as the most recently used
+    pub(crate) fn get(&mut self, filter: &Filter) -> Option<Vec<EventId>> {
+        let tick: u64 = self.next_tick();
+        let entry: &mut Entry = self.entries.get_mut(filter)?;
+
+        if let Some(filter) = self.order.remove(&entry.last_used) {
+            self.order.insert(tick, filter);
+        }
+        entry.last_used = tick;
+
+        Some(entry.ids.clone())
+    }
+
+    /// Cache the IDs returned by the filter
+    ///
+    /// Skipped if the cache was invalidated after `generation`,
+    /// since the IDs may be computed from outdated data.
+    pub(crate) fn insert(&mut self, filter: Filter, ids: Vec<EventId>, generation: u64) {
+        if generation != self.generation {
+            return;
+        }
+
+        let tick: u64 = self.next_tick();
+        let entry: Entry = Entry {
+            ids,
+            last_used: tick,
+        };
+
+        if let Some(old) = self.entries.insert(filter.clone(), entry) {
+            self.order.remove(&old.last_used);
+        }
+        self.order.insert(tick, filter);
+
+        // Evict the least recently used
+        while self.entries.len() > self.capacity.get() {
+            match self.order.pop_first() {
+                Some((_, filter)) => {
+                    self.entries.remove(&filter);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Invalidate the entries that may be affected by a new event
+    ///
+    /// The entries whose filter matches the event are removed.
+    /// A replaceable or addressable event may also replace an event returned by a filter it doesn't match,
+    /// so, for these, all the entries that may involve the event kind and author are removed too.
+    /// A deletion request (NIP-09) may affect any entry, so the whole cache is cleared.
+    pub(crate) fn invalidate(&mut self, event: &Event) {
+        if event.kind == Kind::EventDeletion {
+            self.clear();
+            return;
+        }
+
+        let replaceable: bool = event.kind.is_replaceable() || event.kind.is_addressable();
+
+        self.generation += 1;
+        self.entries.retain(|filter, _| {
+            if filter.match_event(event) {
+                return false;
+            }
+
+            if !replaceable {
+                return true;
+            }
+
+            let kind_match: bool = filter.kinds.as_ref().map_or(true, |kinds| {
+                kinds.is_empty() || kinds.contains(&event.kind)
+            });
+            let author_match: bool = filter.authors.as_ref().map_or(true, |authors| {
+                authors.is_empty() || authors.contains(&event.pubkey)
+            });
+            !(kind_match && author_match)
+        });
+
+        let entries = &self.entries;
+        self.order.retain(|_, filter| entries.contains_key(filter));
+    }
+
+    /// Remove all the entries
+    pub(crate) fn clear(&mut self) {
+        self.generation += 1;
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr::Keys;
+
+    use super::*;
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut cache = QueryCache::new(NonZeroUsize::new(2).unwrap());
+        let a = Filter::new().author(Keys::generate().public_key);
+        let b = Filter::new().author(Keys::generate().public_key);
+        let c = Filter::new().author(Keys::generate().public_key);
+
+        cache.insert(a.clone(), Vec::new(), 0);
+        cache.insert(b.clone(), Vec::new(), 0);
+
+        // `a` becomes the most recently used, so `b` is evicted
+        assert!(cache.get(&a).is_some());
+        cache.insert(c.clone(), Vec::new(), 0);
+
+        assert!(cache.get(&a).is_some());
+        assert!(cache.get(&b).is_none());
+        assert!(cache.get(&c).is_some());
+        assert_eq!(cache.entries.len(), cache.order.len());
+    }
+}
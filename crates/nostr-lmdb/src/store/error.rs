@@ -6,8 +6,9 @@
 use std::{fmt, io};
 
 use async_utility::tokio::task::JoinError;
+use heed::MdbError;
 use nostr::{key, secp256k1};
-use nostr_database::flatbuffers;
+use nostr_database::{flatbuffers, DatabaseError};
 
 #[derive(Debug)]
 pub enum Error {
@@ -46,6 +47,25 @@ impl fmt::Display for Error {
     }
 }
 
+impl From<Error> for DatabaseError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Heed(heed::Error::Mdb(MdbError::MapFull)) => Self::Full,
+            Error::Heed(heed::Error::Mdb(MdbError::Corrupted | MdbError::PageNotFound)) => {
+                Self::Corrupted
+            }
+            Error::Io(e) | Error::Heed(heed::Error::Io(e))
+                if e.kind() == io::ErrorKind::PermissionDenied =>
+            {
+                Self::ReadOnly
+            }
+            Error::FlatBuffers(..) => Self::Corrupted,
+            Error::NotFound => Self::NotFound,
+            e => Self::backend(e),
+        }
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Self {
         Self::Io(e)
@@ -81,3 +101,31 @@ impl From<secp256k1::Error> for Error {
         Self::Secp256k1(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_database_error() {
+        let e = Error::Heed(heed::Error::Mdb(MdbError::MapFull));
+        assert!(matches!(DatabaseError::from(e), DatabaseError::Full));
+
+        let e = Error::Heed(heed::Error::Mdb(MdbError::Corrupted));
+        assert!(matches!(DatabaseError::from(e), DatabaseError::Corrupted));
+
+        let e = Error::Io(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(DatabaseError::from(e), DatabaseError::ReadOnly));
+
+        assert!(matches!(
+            DatabaseError::from(Error::NotFound),
+            DatabaseError::NotFound
+        ));
+
+        // Catch-all
+        assert!(matches!(
+            DatabaseError::from(Error::MutexPoisoned),
+            DatabaseError::Backend(..)
+        ));
+    }
+}
@@ -22,6 +22,25 @@ fn reverse_and_conv_to_be64(created_at: &Timestamp) -> [u8; 8] {
     created_at.to_be_bytes()
 }
 
+/// Len of the `reverse_created_at(8)` + `event_id(32)` tail of the index keys
+pub const INDEX_KEY_TAIL_LEN: usize = CREATED_AT_BE + EventId::LEN;
+
+/// Get the `reverse_created_at(8)` + `event_id(32)` tail of an index key
+///
+/// Shared by all the indexes, except the expiration one.
+/// The tails sort the events newest first, then by ID (Lookup ID: EVENT_ORD_IMPL).
+pub fn index_key_tail(key: &[u8]) -> Option<[u8; INDEX_KEY_TAIL_LEN]> {
+    let start: usize = key.len().checked_sub(INDEX_KEY_TAIL_LEN)?;
+    key[start..].try_into().ok()
+}
+
+/// Get the event ID from an index key tail
+pub fn event_id_from_index_key_tail(tail: &[u8; INDEX_KEY_TAIL_LEN]) -> EventId {
+    let mut id: [u8; EventId::LEN] = [0; EventId::LEN];
+    id.copy_from_slice(&tail[CREATED_AT_BE..]);
+    EventId::from_byte_array(id)
+}
+
 /// Extend the key with the `tag_value` (fixed len of 182 bytes)
 fn extend_key_with_tag_value(key: &mut Vec<u8>, len: usize, tag_value: &str) {
     let tag_value: &[u8] = tag_value.as_bytes();
@@ -161,6 +180,18 @@ pub fn make_ktc_index_key(
     key
 }
 
+/// Make Expiration + ID index key (for looking up the expired events)
+///
+/// ## Structure
+///
+/// `expiration(8)` + `event_id(32)`
+pub fn make_expiration_index_key(expiration: &Timestamp, event_id: &[u8; EventId::LEN]) -> Vec<u8> {
+    let mut key: Vec<u8> = Vec::with_capacity(CREATED_AT_BE + EventId::LEN);
+    key.extend(expiration.as_u64().to_be_bytes());
+    key.extend(event_id);
+    key
+}
+
 /// Make coordinate index key
 ///
 /// ## Structure
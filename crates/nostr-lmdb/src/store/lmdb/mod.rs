@@ -3,17 +3,17 @@
 // Copyright (c) 2023-2025 Rust Nostr Developers
 // Distributed under the MIT software license
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashSet};
 use std::iter;
-use std::ops::Bound;
+use std::ops::{Bound, ControlFlow};
 use std::path::Path;
 
-use heed::byteorder::NativeEndian;
-use heed::types::{Bytes, Unit, U64};
+use heed::byteorder::{BigEndian, NativeEndian};
+use heed::types::{Bytes, Str, Unit, U64};
 use heed::{Database, Env, EnvFlags, EnvOpenOptions, RoRange, RoTxn, RwTxn};
 use nostr::prelude::*;
 use nostr_database::flatbuffers::FlatBufferDecodeBorrowed;
-use nostr_database::{FlatBufferBuilder, FlatBufferEncode};
+use nostr_database::{FlatBufferBuilder, FlatBufferEncode, PruneReport};
 
 pub(super) mod index;
 
@@ -23,13 +23,16 @@ use super::types::DatabaseFilter;
 const EVENT_ID_ALL_ZEROS: [u8; 32] = [0; 32];
 const EVENT_ID_ALL_255: [u8; 32] = [255; 32];
 
+/// Metadata key of the last assigned sequence number
+const LAST_SEQ_KEY: &str = "last-seq";
+
 // 64-bit
 #[cfg(target_pointer_width = "64")]
-const MAP_SIZE: usize = 1024 * 1024 * 1024 * 32; // 32GB
+pub(crate) const MAP_SIZE: usize = 1024 * 1024 * 1024 * 32; // 32GB
 
 // 32-bit
 #[cfg(target_pointer_width = "32")]
-const MAP_SIZE: usize = 0xFFFFF000; // 4GB (2^32-4096)
+pub(crate) const MAP_SIZE: usize = 0xFFFFF000; // 4GB (2^32-4096)
 
 #[derive(Debug, Clone)]
 pub(crate) struct Lmdb {
@@ -49,14 +52,30 @@ pub(crate) struct Lmdb {
     atc_index: Database<Bytes, Bytes>, // <Index>, Event ID
     /// Kind + Tag + CreatedAt + ID index
     ktc_index: Database<Bytes, Bytes>, // <Index>, Event ID
+    /// Expiration + ID index (NIP-40)
+    expiration_index: Database<Bytes, Bytes>, // <Index>, Event ID
     /// Deleted IDs
     deleted_ids: Database<Bytes, Unit>, // Event ID
     /// Deleted coordinates
     deleted_coordinates: Database<Bytes, U64<NativeEndian>>, // Coordinate, UNIX timestamp
+    /// Insertion sequence
+    seq_index: Database<U64<BigEndian>, Bytes>, // Sequence number, Event ID
+    /// Sequence number of the stored events
+    seq_ids: Database<Bytes, U64<BigEndian>>, // Event ID, Sequence number
+    /// Metadata, not cleared by the wipe
+    ///
+    /// Keeps the last assigned sequence number, so the sequence numbers are never reused.
+    metadata: Database<Str, U64<BigEndian>>, // Key, Value
+}
+
+/// Check if the query of the filter can't use any index other than the `created_at` one
+#[inline]
+fn is_scrape(filter: &DatabaseFilter) -> bool {
+    filter.ids.is_empty() && filter.authors.is_empty() && filter.generic_tags.is_empty()
 }
 
 impl Lmdb {
-    pub(crate) fn new<P>(path: P) -> Result<Self, Error>
+    pub(crate) fn new<P>(path: P, map_size: usize) -> Result<Self, Error>
     where
         P: AsRef<Path>,
     {
@@ -64,8 +83,8 @@ impl Lmdb {
         let env: Env = unsafe {
             EnvOpenOptions::new()
                 .flags(EnvFlags::NO_TLS)
-                .max_dbs(9)
-                .map_size(MAP_SIZE)
+                .max_dbs(14)
+                .map_size(map_size)
                 .open(path)?
         };
 
@@ -107,6 +126,15 @@ impl Lmdb {
             .types::<Bytes, Bytes>()
             .name("ktci")
             .create(&mut txn)?;
+        // Check if the expiration index must be populated (databases created before it existed)
+        let expiration_index_exists: bool = env
+            .open_database::<Bytes, Bytes>(&txn, Some("expiration"))?
+            .is_some();
+        let expiration_index = env
+            .database_options()
+            .types::<Bytes, Bytes>()
+            .name("expiration")
+            .create(&mut txn)?;
         let deleted_ids = env
             .database_options()
             .types::<Bytes, Unit>()
@@ -117,12 +145,24 @@ impl Lmdb {
             .types::<Bytes, U64<NativeEndian>>()
             .name("deleted-coordinates")
             .create(&mut txn)?;
+        let seq_index = env
+            .database_options()
+            .types::<U64<BigEndian>, Bytes>()
+            .name("seq")
+            .create(&mut txn)?;
+        let seq_ids = env
+            .database_options()
+            .types::<Bytes, U64<BigEndian>>()
+            .name("seq-ids")
+            .create(&mut txn)?;
+        let metadata = env
+            .database_options()
+            .types::<Str, U64<BigEndian>>()
+            .name("metadata")
+            .create(&mut txn)?;
 
-        // Commit changes
-        txn.commit()?;
-
-        Ok(Self {
-            env,
+        let lmdb: Self = Self {
+            env: env.clone(),
             events,
             ci_index,
             tc_index,
@@ -130,9 +170,99 @@ impl Lmdb {
             akc_index,
             atc_index,
             ktc_index,
+            expiration_index,
             deleted_ids,
             deleted_coordinates,
-        })
+            seq_index,
+            seq_ids,
+            metadata,
+        };
+
+        if !expiration_index_exists {
+            lmdb.populate_expiration_index(&mut txn)?;
+        }
+
+        // Databases created before the sequence counter existed
+        if lmdb.metadata.get(&txn, LAST_SEQ_KEY)?.is_none() {
+            lmdb.populate_seq_index(&mut txn)?;
+        }
+
+        // Commit changes
+        txn.commit()?;
+
+        Ok(lmdb)
+    }
+
+    /// Index the expiration of the stored events
+    fn populate_expiration_index(&self, txn: &mut RwTxn) -> Result<(), Error> {
+        let mut keys: Vec<(Vec<u8>, [u8; 32])> = Vec::new();
+
+        // The main database includes the names of the other databases: walk the `ci` index instead
+        for result in self.ci_index.iter(txn)? {
+            let (_key, id) = result?;
+            let event = self.get_event_by_id(txn, id)?.ok_or(Error::NotFound)?;
+
+            if let Some(expiration) = event.expiration() {
+                let key: Vec<u8> = index::make_expiration_index_key(&expiration, event.id);
+                keys.push((key, *event.id));
+            }
+        }
+
+        for (key, id) in keys.iter() {
+            self.expiration_index.put(txn, key, id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Assign the sequence numbers to the stored events and initialize the counter
+    ///
+    /// The entries of the removed events are dropped and the events without a sequence number
+    /// are appended, oldest first.
+    fn populate_seq_index(&self, txn: &mut RwTxn) -> Result<(), Error> {
+        let mut dead: Vec<u64> = Vec::new();
+        let mut live: Vec<(u64, [u8; 32])> = Vec::new();
+
+        for result in self.seq_index.iter(txn)? {
+            let (seq, id) = result?;
+            match self.get_event_by_id(txn, id)? {
+                Some(event) => live.push((seq, *event.id)),
+                None => dead.push(seq),
+            }
+        }
+
+        let mut last: u64 = match self.seq_index.last(txn)? {
+            Some((last, _)) => last,
+            None => 0,
+        };
+
+        for seq in dead.iter() {
+            self.seq_index.delete(txn, seq)?;
+        }
+
+        for (seq, id) in live.iter() {
+            self.seq_ids.put(txn, id, seq)?;
+        }
+
+        // The `ci` keys are sorted newest first
+        let mut missing: Vec<[u8; 32]> = Vec::new();
+        for result in self.ci_index.rev_iter(txn)? {
+            let (_key, id) = result?;
+            if self.seq_ids.get(txn, id)?.is_none() {
+                let event = self.get_event_by_id(txn, id)?.ok_or(Error::NotFound)?;
+                missing.push(*event.id);
+            }
+        }
+
+        for id in missing.iter() {
+            last += 1;
+            self.seq_index.put(txn, &last, id)?;
+            self.seq_ids.put(txn, id, &last)?;
+        }
+
+        self.metadata.put(txn, LAST_SEQ_KEY, &last)?;
+
+        Ok(())
     }
 
     /// Get a read transaction
@@ -141,12 +271,27 @@ impl Lmdb {
         Ok(self.env.read_txn()?)
     }
 
+    /// Get a read transaction not bound to the lifetime of the environment
+    ///
+    /// The transaction occupies a reader slot and prevents the reuse of the pages
+    /// freed by the subsequent writes, until dropped.
+    #[inline]
+    pub(crate) fn static_read_txn(&self) -> Result<RoTxn<'static>, Error> {
+        Ok(self.env.clone().static_read_txn()?)
+    }
+
     /// Get a write transaction
     #[inline]
     pub(crate) fn write_txn(&self) -> Result<RwTxn, Error> {
         Ok(self.env.write_txn()?)
     }
 
+    /// Flush the data buffers to disk
+    #[inline]
+    pub(crate) fn force_sync(&self) -> Result<(), Error> {
+        Ok(self.env.force_sync()?)
+    }
+
     /// Store and index the event
     pub(crate) fn store(
         &self,
@@ -159,6 +304,12 @@ impl Lmdb {
         // Store event
         self.events.put(txn, id, event.encode(fbb))?;
 
+        // Assign the next sequence number
+        let seq: u64 = self.metadata.get(txn, LAST_SEQ_KEY)?.unwrap_or_default() + 1;
+        self.metadata.put(txn, LAST_SEQ_KEY, &seq)?;
+        self.seq_index.put(txn, &seq, id)?;
+        self.seq_ids.put(txn, id, &seq)?;
+
         // Index by created_at and id
         let ci_index_key: Vec<u8> =
             index::make_ci_index_key(&event.created_at, event.id.as_bytes());
@@ -214,6 +365,13 @@ impl Lmdb {
             }
         }
 
+        // Index by expiration (with id)
+        if let Some(expiration) = event.tags.expiration() {
+            let expiration_index_key: Vec<u8> =
+                index::make_expiration_index_key(expiration, event.id.as_bytes());
+            self.expiration_index.put(txn, &expiration_index_key, id)?;
+        }
+
         Ok(())
     }
 
@@ -221,6 +379,11 @@ impl Lmdb {
     pub(crate) fn remove(&self, txn: &mut RwTxn, event: &EventBorrow) -> Result<(), Error> {
         self.events.delete(txn, event.id)?;
 
+        if let Some(seq) = self.seq_ids.get(txn, event.id)? {
+            self.seq_index.delete(txn, &seq)?;
+            self.seq_ids.delete(txn, event.id)?;
+        }
+
         let ci_index_key: Vec<u8> = index::make_ci_index_key(&event.created_at, event.id);
         self.ci_index.delete(txn, &ci_index_key)?;
 
@@ -261,19 +424,45 @@ impl Lmdb {
             }
         }
 
+        if let Some(expiration) = event.expiration() {
+            let expiration_index_key: Vec<u8> =
+                index::make_expiration_index_key(&expiration, event.id);
+            self.expiration_index.delete(txn, &expiration_index_key)?;
+        }
+
         Ok(())
     }
 
+    /// Clear all the databases
+    ///
+    /// All the databases, except the metadata, are cleared in the same write transaction,
+    /// without closing them, so the open handles stay valid. The in-flight read transactions keep seeing the pre-wipe
+    /// snapshot until they finish, while the ones opened after the commit see empty databases.
     pub(crate) fn wipe(&self, txn: &mut RwTxn) -> Result<(), Error> {
-        self.events.clear(txn)?;
+        // The main database includes the names of the other databases: clearing it would
+        // invalidate their handles, so delete the events one by one, walking the `ci` index
+        let mut ids: Vec<[u8; 32]> = Vec::new();
+        for result in self.ci_index.iter(txn)? {
+            let (_key, id) = result?;
+            let event = self.get_event_by_id(txn, id)?.ok_or(Error::NotFound)?;
+            ids.push(*event.id);
+        }
+
+        for id in ids.iter() {
+            self.events.delete(txn, id)?;
+        }
+
         self.ci_index.clear(txn)?;
         self.tc_index.clear(txn)?;
         self.ac_index.clear(txn)?;
         self.akc_index.clear(txn)?;
         self.atc_index.clear(txn)?;
         self.ktc_index.clear(txn)?;
+        self.expiration_index.clear(txn)?;
         self.deleted_ids.clear(txn)?;
         self.deleted_coordinates.clear(txn)?;
+        self.seq_index.clear(txn)?;
+        self.seq_ids.clear(txn)?;
         Ok(())
     }
 
@@ -282,6 +471,37 @@ impl Lmdb {
         Ok(self.get_event_by_id(txn, event_id)?.is_some())
     }
 
+    /// Get the events inserted after the `seq` sequence number, in insertion order
+    ///
+    /// Return the events and the sequence number of the last scanned entry
+    /// (`seq` if no newer entries exist).
+    pub(crate) fn events_since_seq<'a>(
+        &self,
+        txn: &'a RoTxn,
+        seq: u64,
+        limit: usize,
+    ) -> Result<(Vec<EventBorrow<'a>>, u64), Error> {
+        let mut events: Vec<EventBorrow<'a>> = Vec::new();
+        let mut last: u64 = seq;
+
+        let range = (Bound::Excluded(seq), Bound::Unbounded);
+        for result in self.seq_index.range(txn, &range)? {
+            if events.len() >= limit {
+                break;
+            }
+
+            let (current, id) = result?;
+            last = current;
+
+            // Skip the removed events
+            if let Some(event) = self.get_event_by_id(txn, id)? {
+                events.push(event);
+            }
+        }
+
+        Ok((events, last))
+    }
+
     #[inline]
     pub(crate) fn get_event_by_id<'a>(
         &self,
@@ -294,19 +514,39 @@ impl Lmdb {
         }
     }
 
-    pub fn delete(&self, read_txn: &RoTxn, txn: &mut RwTxn, filter: Filter) -> Result<(), Error> {
-        let events = self.query(read_txn, filter)?;
+    pub fn delete(
+        &self,
+        read_txn: &RoTxn,
+        txn: &mut RwTxn,
+        filter: Filter,
+    ) -> Result<usize, Error> {
+        // Expired events must be deleted too
+        let events = self.internal_query(read_txn, filter, true)?;
+        let mut deleted: usize = 0;
         for event in events.into_iter() {
             self.remove(txn, &event)?;
+            deleted += 1;
         }
-        Ok(())
+        Ok(deleted)
     }
 
     /// Find all events that match the filter
+    ///
+    /// Expired events (NIP-40) are excluded.
+    #[inline]
     pub fn query<'a>(
         &self,
         txn: &'a RoTxn,
         filter: Filter,
+    ) -> Result<Box<dyn Iterator<Item = EventBorrow<'a>> + 'a>, Error> {
+        self.internal_query(txn, filter, false)
+    }
+
+    fn internal_query<'a>(
+        &self,
+        txn: &'a RoTxn,
+        filter: Filter,
+        include_expired: bool,
     ) -> Result<Box<dyn Iterator<Item = EventBorrow<'a>> + 'a>, Error> {
         if let (Some(since), Some(until)) = (filter.since, filter.until) {
             if since > until {
@@ -314,6 +554,11 @@ impl Lmdb {
             }
         }
 
+        // A tag with no values matches no event
+        if filter.has_empty_tags() {
+            return Ok(Box::new(iter::empty()));
+        }
+
         // We insert into a BTreeSet to keep them time-ordered
         let mut output: BTreeSet<EventBorrow<'a>> = BTreeSet::new();
 
@@ -321,7 +566,11 @@ impl Lmdb {
         let since = filter.since.unwrap_or_else(Timestamp::min);
         let until = filter.until.unwrap_or_else(Timestamp::max);
 
-        let filter: DatabaseFilter = filter.into();
+        let mut filter: DatabaseFilter = filter.into();
+
+        if include_expired {
+            filter.expired_at = None;
+        }
 
         if !filter.ids.is_empty() {
             // Fetch by id
@@ -501,6 +750,293 @@ impl Lmdb {
         })
     }
 
+    /// Walk the events matching the filter, newest first
+    ///
+    /// The filters with indexed fields use the same indexes of [`Lmdb::query`],
+    /// the others scan the `created_at` index in the `since`-`until` range, without collecting the events.
+    pub fn for_each<'a, F>(&self, txn: &'a RoTxn, filter: Filter, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&EventBorrow<'a>) -> ControlFlow<()>,
+    {
+        if let (Some(since), Some(until)) = (filter.since, filter.until) {
+            if since > until {
+                return Ok(());
+            }
+        }
+
+        // A tag with no values matches no event
+        if filter.has_empty_tags() {
+            return Ok(());
+        }
+
+        let limit: Option<usize> = filter.limit;
+        let since = filter.since.unwrap_or_else(Timestamp::min);
+        let until = filter.until.unwrap_or_else(Timestamp::max);
+
+        let db_filter: DatabaseFilter = filter.clone().into();
+
+        if !is_scrape(&db_filter) {
+            for event in self.query(txn, filter)? {
+                if f(&event).is_break() {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        let filter: DatabaseFilter = db_filter;
+
+        let mut count: usize = 0;
+
+        for result in self.ci_iter(txn, &since, &until)? {
+            // Stop if limited
+            if let Some(limit) = limit {
+                if count >= limit {
+                    break;
+                }
+            }
+
+            let (_key, value) = result?;
+            let event = self.get_event_by_id(txn, value)?.ok_or(Error::NotFound)?;
+
+            if filter.match_event(&event) {
+                count += 1;
+
+                if f(&event).is_break() {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the IDs of the events matching the filter
+    ///
+    /// The filters with only `authors`, `kinds` (along with `authors`), `since`, `until` and `limit`
+    /// are resolved by walking the index keys, which end with the event ID, without decoding the events.
+    /// The others fall back to [`Lmdb::query`].
+    pub fn event_ids(&self, txn: &RoTxn, filter: Filter) -> Result<BTreeSet<EventId>, Error> {
+        // A tag with no values matches no event
+        if filter.has_empty_tags() {
+            return Ok(BTreeSet::new());
+        }
+
+        let filter: Filter = filter.normalized();
+
+        let index_only: bool = filter.ids.is_none()
+            && filter.search.is_none()
+            && filter.generic_tags.is_empty()
+            && filter.multi_letter_tags.is_empty()
+            && (filter.kinds.is_none() || filter.authors.is_some());
+
+        if !index_only {
+            return Ok(self
+                .query(txn, filter)?
+                .map(|e| EventId::from_byte_array(*e.id))
+                .collect());
+        }
+
+        if let (Some(since), Some(until)) = (filter.since, filter.until) {
+            if since > until {
+                return Ok(BTreeSet::new());
+            }
+        }
+
+        let limit: Option<usize> = filter.limit;
+        let since = filter.since.unwrap_or_else(Timestamp::min);
+        let until = filter.until.unwrap_or_else(Timestamp::max);
+
+        // The expired events are excluded, as in the queries.
+        // The candidates are decoded to check their expiration only if some stored event is expired.
+        let now: Timestamp = Timestamp::now();
+        let check_expiration: bool = self.has_expired(txn, &now)?;
+
+        let mut iters: Vec<RoRange<Bytes, Bytes>> = Vec::new();
+        match (&filter.authors, &filter.kinds) {
+            (Some(authors), Some(kinds)) => {
+                for author in authors.iter() {
+                    for kind in kinds.iter() {
+                        iters.push(self.akc_iter(
+                            txn,
+                            author.as_bytes(),
+                            kind.as_u16(),
+                            since,
+                            until,
+                        )?);
+                    }
+                }
+            }
+            (Some(authors), None) => {
+                for author in authors.iter() {
+                    iters.push(self.ac_iter(txn, author.as_bytes(), since, until)?);
+                }
+            }
+            _ => iters.push(self.ci_iter(txn, &since, &until)?),
+        }
+
+        // Every range is sorted, so at most `limit` keys are needed from each one
+        let mut tails: BTreeSet<[u8; index::INDEX_KEY_TAIL_LEN]> = BTreeSet::new();
+        for iter in iters.into_iter() {
+            let mut count: usize = 0;
+
+            for result in iter {
+                if let Some(limit) = limit {
+                    if count >= limit {
+                        break;
+                    }
+                }
+
+                let (key, id) = result?;
+
+                if check_expiration {
+                    let event = self.get_event_by_id(txn, id)?.ok_or(Error::NotFound)?;
+                    if event.is_expired_at(&now) {
+                        continue;
+                    }
+                }
+
+                tails.insert(index::index_key_tail(key).ok_or(Error::NotFound)?);
+                count += 1;
+            }
+        }
+
+        let tails = tails.iter().take(limit.unwrap_or(usize::MAX));
+        Ok(tails.map(index::event_id_from_index_key_tail).collect())
+    }
+
+    /// Check if any event expired at `now` is not removed yet (NIP-40)
+    fn has_expired(&self, txn: &RoTxn, now: &Timestamp) -> Result<bool, Error> {
+        let end: Vec<u8> = index::make_expiration_index_key(now, &EVENT_ID_ALL_ZEROS);
+        let range = (Bound::Unbounded, Bound::Excluded(end.as_slice()));
+        Ok(self.expiration_index.range(txn, &range)?.next().is_some())
+    }
+
+    /// Remove all the events except the `keep_newest` ones
+    ///
+    /// Return the number of removed events.
+    pub fn truncate_to(
+        &self,
+        read_txn: &RoTxn,
+        txn: &mut RwTxn,
+        keep_newest: usize,
+    ) -> Result<usize, Error> {
+        let mut removed: usize = 0;
+
+        // Lookup ID: EVENT_ORD_IMPL
+        let iter = self.ci_iter(read_txn, &Timestamp::min(), &Timestamp::max())?;
+        for result in iter.skip(keep_newest) {
+            let (_key, value) = result?;
+            let event = self
+                .get_event_by_id(read_txn, value)?
+                .ok_or(Error::NotFound)?;
+            self.remove(txn, &event)?;
+            removed += 1;
+        }
+
+        Ok(removed)
+    }
+
+    /// Remove the events superseded by a newer replaceable or addressable version,
+    /// or targeted by a stored deletion (NIP-09)
+    pub fn prune(&self, read_txn: &RoTxn, txn: &mut RwTxn) -> Result<PruneReport, Error> {
+        let mut report: PruneReport = PruneReport::default();
+        let mut live: HashSet<Coordinate> = HashSet::new();
+
+        // Newest first and, on ties, lowest ID first:
+        // the first event of every coordinate is the live one.
+        // Lookup ID: EVENT_ORD_IMPL
+        for result in self.ci_iter(read_txn, &Timestamp::min(), &Timestamp::max())? {
+            let (_key, value) = result?;
+            let event = self
+                .get_event_by_id(read_txn, value)?
+                .ok_or(Error::NotFound)?;
+
+            let id: EventId = EventId::from_byte_array(*event.id);
+            if self.is_deleted(read_txn, &id)? {
+                self.remove(txn, &event)?;
+                report.deleted += 1;
+                continue;
+            }
+
+            let kind: Kind = Kind::from_u16(event.kind);
+            if kind.is_replaceable() || kind.is_addressable() {
+                let owned: Event = event.clone().into_owned();
+                let coordinate: CoordinateBorrow = match owned.coordinate() {
+                    Some(coordinate) => coordinate,
+                    None => continue,
+                };
+
+                let deleted: bool = matches!(
+                    self.when_is_coordinate_deleted(read_txn, &coordinate)?,
+                    Some(time) if owned.created_at <= time
+                );
+
+                if deleted {
+                    self.remove(txn, &event)?;
+                    report.deleted += 1;
+                } else if !live.insert(coordinate.into_owned()) {
+                    self.remove(txn, &event)?;
+                    report.replaced += 1;
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Remove the events expired at `now` (NIP-40)
+    ///
+    /// The expired events are looked up in the expiration index and removed in batches of `batch_size`,
+    /// each one in its own write transaction, to avoid holding the write lock for too long.
+    ///
+    /// Return the number of removed events.
+    pub fn remove_expired(&self, now: &Timestamp, batch_size: usize) -> Result<usize, Error> {
+        let batch_size: usize = batch_size.max(1);
+        let end: Vec<u8> = index::make_expiration_index_key(now, &EVENT_ID_ALL_ZEROS);
+        let range = (Bound::Unbounded, Bound::Excluded(end.as_slice()));
+
+        let mut removed: usize = 0;
+
+        loop {
+            // Acquire the write transaction before the read one (see `Store::save_event`)
+            let mut txn = self.write_txn()?;
+            let read_txn = self.read_txn()?;
+
+            let mut scanned: usize = 0;
+
+            for result in self
+                .expiration_index
+                .range(&read_txn, &range)?
+                .take(batch_size)
+            {
+                let (key, id) = result?;
+                scanned += 1;
+
+                match self.get_event_by_id(&read_txn, id)? {
+                    Some(event) => {
+                        self.remove(&mut txn, &event)?;
+                        removed += 1;
+                    }
+                    // Stale entry
+                    None => {
+                        self.expiration_index.delete(&mut txn, key)?;
+                    }
+                }
+            }
+
+            read_txn.commit()?;
+            txn.commit()?;
+
+            // No more expired events
+            if scanned < batch_size {
+                break;
+            }
+        }
+
+        Ok(removed)
+    }
+
     fn iterate_filter_until_limit<'a>(
         &self,
         txn: &'a RoTxn,
@@ -834,3 +1370,92 @@ impl Lmdb {
         Ok(self.ktc_index.range(txn, &range)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nostr_database::DatabaseError;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_populate_seq_index() {
+        let temp = TempDir::new().unwrap();
+        let db = Lmdb::new(temp.path(), MAP_SIZE).unwrap();
+        let mut fbb = FlatBufferBuilder::new();
+        let keys = Keys::generate();
+
+        let events: Vec<Event> = (0..4)
+            .map(|i| {
+                EventBuilder::text_note(format!("Note {i}"))
+                    .custom_created_at(Timestamp::from_secs(1_000 + i))
+                    .sign_with_keys(&keys)
+                    .unwrap()
+            })
+            .collect();
+
+        let mut txn = db.write_txn().unwrap();
+        for event in events[..3].iter() {
+            db.store(&mut txn, &mut fbb, event).unwrap();
+        }
+
+        // Simulate a database created before the sequence counter:
+        // a dead entry and an event without sequence number
+        db.metadata.delete(&mut txn, LAST_SEQ_KEY).unwrap();
+        db.seq_ids.clear(&mut txn).unwrap();
+        db.seq_index.delete(&mut txn, &3).unwrap();
+        db.seq_index.put(&mut txn, &5, &EVENT_ID_ALL_255).unwrap();
+
+        db.populate_seq_index(&mut txn).unwrap();
+        txn.commit().unwrap();
+
+        let txn = db.read_txn().unwrap();
+        let (stored, last) = db.events_since_seq(&txn, 0, 10).unwrap();
+        let ids: Vec<EventId> = stored
+            .iter()
+            .map(|e| EventId::from_byte_array(*e.id))
+            .collect();
+        assert_eq!(ids, events[..3].iter().map(|e| e.id).collect::<Vec<_>>());
+        assert_eq!(last, 6);
+        drop(txn);
+
+        // The counter continues after the backfilled entries
+        let mut txn = db.write_txn().unwrap();
+        db.store(&mut txn, &mut fbb, &events[3]).unwrap();
+        txn.commit().unwrap();
+
+        let txn = db.read_txn().unwrap();
+        let (stored, last) = db.events_since_seq(&txn, 6, 10).unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].id, events[3].id.as_bytes());
+        assert_eq!(last, 7);
+    }
+
+    #[test]
+    fn test_map_full() {
+        let temp = TempDir::new().unwrap();
+        let db = Lmdb::new(temp.path(), 256 * 1024).unwrap();
+        let mut fbb = FlatBufferBuilder::new();
+        let keys = Keys::generate();
+
+        let mut result: Result<(), Error> = Ok(());
+        for i in 0..10_000 {
+            let event = EventBuilder::text_note("a".repeat(1_000 + i))
+                .sign_with_keys(&keys)
+                .unwrap();
+
+            result = db.write_txn().and_then(|mut txn| {
+                db.store(&mut txn, &mut fbb, &event)?;
+                txn.commit()?;
+                Ok(())
+            });
+
+            if result.is_err() {
+                break;
+            }
+        }
+
+        let e = result.unwrap_err();
+        assert!(matches!(DatabaseError::from(e), DatabaseError::Full));
+    }
+}
@@ -3,27 +3,62 @@
 // Copyright (c) 2023-2025 Rust Nostr Developers
 // Distributed under the MIT software license
 
+use std::collections::{BTreeSet, HashSet};
 use std::fs;
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
 
-use async_utility::task;
+use async_utility::futures_util::stream::AbortHandle;
+use async_utility::{task, time};
 use heed::{RoTxn, RwTxn};
 use nostr_database::prelude::*;
+use tokio::sync::mpsc;
 
+mod cache;
 mod error;
 mod lmdb;
+mod snapshot;
 mod types;
 
+use self::cache::QueryCache;
 use self::error::Error;
-use self::lmdb::{index, Lmdb};
+use self::lmdb::{index, Lmdb, MAP_SIZE};
+pub use self::snapshot::Snapshot;
 
 type Fbb = Arc<Mutex<FlatBufferBuilder<'static>>>;
+/// Shared with the expiration sweeper
+type SharedQueryCache = Arc<Mutex<Option<QueryCache>>>;
+
+/// Max number of expired events removed in a single write transaction
+const EXPIRATION_SWEEP_BATCH_SIZE: usize = 1_000;
+/// Max number of events buffered between the walk and the `for_each` callback
+const FOR_EACH_CHANNEL_SIZE: usize = 256;
+
+/// Newest event wins; on timestamp ties, the lowest ID is kept,
+/// or the stored one if `keep_stored_on_tie` is set.
+#[inline]
+fn has_precedence(stored: &EventBorrow, new: &Event, keep_stored_on_tie: bool) -> bool {
+    stored.created_at > new.created_at
+        || (stored.created_at == new.created_at
+            && (keep_stored_on_tie || stored.id <= new.id.as_bytes()))
+}
+
+fn with_query_cache<F, R>(query_cache: &SharedQueryCache, f: F) -> Result<Option<R>, Error>
+where
+    F: FnOnce(&mut QueryCache) -> R,
+{
+    let mut cache = query_cache.lock().map_err(|_| Error::MutexPoisoned)?;
+    Ok(cache.as_mut().map(f))
+}
 
 #[derive(Debug)]
 pub struct Store {
     db: Lmdb,
     fbb: Fbb,
+    query_cache: SharedQueryCache,
 }
 
 impl Store {
@@ -37,11 +72,31 @@ impl Store {
         fs::create_dir_all(path)?;
 
         Ok(Store {
-            db: Lmdb::new(path)?,
+            db: Lmdb::new(path, MAP_SIZE)?,
             fbb: Arc::new(Mutex::new(FlatBufferBuilder::with_capacity(70_000))),
+            query_cache: Arc::new(Mutex::new(None)),
         })
     }
 
+    /// Cache the results of the last `capacity` distinct queries
+    #[inline]
+    pub fn set_query_cache(&mut self, capacity: NonZeroUsize) {
+        // The cache is replaced, so a poisoned one can be recovered
+        let mut cache = self
+            .query_cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *cache = Some(QueryCache::new(capacity));
+    }
+
+    #[inline]
+    fn with_query_cache<F, R>(&self, f: F) -> Result<Option<R>, Error>
+    where
+        F: FnOnce(&mut QueryCache) -> R,
+    {
+        with_query_cache(&self.query_cache, f)
+    }
+
     #[inline]
     async fn interact<F, R>(&self, f: F) -> Result<R, Error>
     where
@@ -64,7 +119,50 @@ impl Store {
     }
 
     /// Store an event.
+    #[inline]
     pub async fn save_event(&self, event: &Event) -> Result<SaveEventStatus, Error> {
+        self.save_event_with_precedence(event, false).await
+    }
+
+    /// Store an event only if newer than the stored replaceable or addressable event.
+    ///
+    /// On timestamp ties, the stored event is kept.
+    #[inline]
+    pub async fn replace_if_newer(&self, event: &Event) -> Result<SaveEventStatus, Error> {
+        self.save_event_with_precedence(event, true).await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "save_event",
+            level = "trace",
+            skip_all,
+            fields(id = %event.id, kind = event.kind.as_u16(), status = tracing::field::Empty)
+        )
+    )]
+    async fn save_event_with_precedence(
+        &self,
+        event: &Event,
+        keep_stored_on_tie: bool,
+    ) -> Result<SaveEventStatus, Error> {
+        let status: SaveEventStatus = self.internal_save_event(event, keep_stored_on_tie).await?;
+
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("status", tracing::field::debug(&status));
+
+        if status.is_success() {
+            self.with_query_cache(|cache| cache.invalidate(event))?;
+        }
+
+        Ok(status)
+    }
+
+    async fn internal_save_event(
+        &self,
+        event: &Event,
+        keep_stored_on_tie: bool,
+    ) -> Result<SaveEventStatus, Error> {
         if event.kind.is_ephemeral() {
             return Ok(SaveEventStatus::Rejected(RejectedReason::Ephemeral));
         }
@@ -73,6 +171,13 @@ impl Store {
         let event = event.clone();
 
         self.interact_with_fbb(move |db, fbb| {
+            // Acquire write transaction
+            //
+            // LMDB allows a single writer at a time: acquiring the write transaction BEFORE the read one
+            // ensures that the read snapshot includes all the previously committed events.
+            // Otherwise, concurrent saves may not see each other (i.e., two replaceable events both stored).
+            let mut txn = db.write_txn()?;
+
             // Acquire read transaction
             let read_txn = db.read_txn()?;
 
@@ -96,16 +201,13 @@ impl Store {
                 }
             }
 
-            // Acquire write transaction
-            let mut txn = db.write_txn()?;
-
             // Remove replaceable events being replaced
             if event.kind.is_replaceable() {
                 // Find replaceable event
                 if let Some(stored) =
                     db.find_replaceable_event(&read_txn, &event.pubkey, event.kind)?
                 {
-                    if stored.created_at > event.created_at {
+                    if has_precedence(&stored, &event, keep_stored_on_tie) {
                         txn.abort();
                         return Ok(SaveEventStatus::Rejected(RejectedReason::Replaced));
                     }
@@ -123,7 +225,7 @@ impl Store {
 
                     // Find param replaceable event
                     if let Some(stored) = db.find_addressable_event(&read_txn, &coordinate)? {
-                        if stored.created_at > event.created_at {
+                        if has_precedence(&stored, &event, keep_stored_on_tie) {
                             txn.abort();
                             return Ok(SaveEventStatus::Rejected(RejectedReason::Replaced));
                         }
@@ -212,6 +314,22 @@ impl Store {
         .await?
     }
 
+    pub async fn get_events_by_ids(&self, ids: Vec<EventId>) -> Result<Vec<Option<Event>>, Error> {
+        self.interact(move |db| {
+            let txn = db.read_txn()?;
+            let mut events: Vec<Option<Event>> = Vec::with_capacity(ids.len());
+            for id in ids.iter() {
+                let event: Option<Event> = db
+                    .get_event_by_id(&txn, id.as_bytes())?
+                    .map(|e| e.into_owned());
+                events.push(event);
+            }
+            txn.commit()?;
+            Ok(events)
+        })
+        .await?
+    }
+
     /// Do we have an event
     pub async fn has_event(&self, id: &EventId) -> Result<bool, Error> {
         let bytes = id.to_bytes();
@@ -250,19 +368,140 @@ impl Store {
         .await?
     }
 
+    /// Take a point-in-time view of the store
+    pub async fn snapshot(&self) -> Result<Snapshot, Error> {
+        self.interact(Snapshot::new).await?
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "count",
+            level = "trace",
+            skip_all,
+            fields(
+                ids = filter.ids.as_ref().map_or(0, |ids| ids.len()),
+                authors = filter.authors.as_ref().map_or(0, |authors| authors.len()),
+                kinds = filter.kinds.as_ref().map_or(0, |kinds| kinds.len()),
+                limit = ?filter.limit,
+                results = tracing::field::Empty,
+            )
+        )
+    )]
     pub async fn count(&self, filter: Filter) -> Result<usize, Error> {
+        let count: usize = self
+            .interact(move |db| -> Result<usize, Error> {
+                let txn = db.read_txn()?;
+                let output = db.query(&txn, filter)?;
+                let len: usize = output.count();
+                txn.commit()?;
+                Ok(len)
+            })
+            .await??;
+
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("results", count);
+
+        Ok(count)
+    }
+
+    pub async fn count_by_filters(&self, filters: Vec<Filter>) -> Result<usize, Error> {
         self.interact(move |db| {
             let txn = db.read_txn()?;
-            let output = db.query(&txn, filter)?;
-            let len: usize = output.count();
+
+            // Union the candidate IDs
+            let mut ids: HashSet<[u8; 32]> = HashSet::new();
+            for filter in filters.into_iter() {
+                let output = db.query(&txn, filter)?;
+                ids.extend(output.map(|e| *e.id));
+            }
+
             txn.commit()?;
-            Ok(len)
+            Ok(ids.len())
         })
         .await?
     }
 
-    // Lookup ID: EVENT_ORD_IMPL
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "query",
+            level = "trace",
+            skip_all,
+            fields(
+                ids = filter.ids.as_ref().map_or(0, |ids| ids.len()),
+                authors = filter.authors.as_ref().map_or(0, |authors| authors.len()),
+                kinds = filter.kinds.as_ref().map_or(0, |kinds| kinds.len()),
+                limit = ?filter.limit,
+                results = tracing::field::Empty,
+            )
+        )
+    )]
     pub async fn query(&self, filter: Filter) -> Result<Events, Error> {
+        // A tag with no values matches no event
+        if filter.has_empty_tags() {
+            return Ok(Events::new(&filter));
+        }
+
+        // Normalize, so the equivalent filters share the cache entry
+        let filter: Filter = filter.normalized();
+
+        // Cache hit, unless some of the cached events were removed or expired in the meantime
+        let cached: Option<Events> = match self.with_query_cache(|cache| cache.get(&filter))? {
+            Some(Some(ids)) => self.query_by_ids(filter.clone(), ids).await?,
+            _ => None,
+        };
+
+        let events: Events = match cached {
+            Some(events) => events,
+            None => match self.with_query_cache(|cache| cache.generation())? {
+                Some(generation) => {
+                    let events: Events = self.internal_query(filter.clone()).await?;
+                    let ids: Vec<EventId> = events.iter().map(|e| e.id).collect();
+                    self.with_query_cache(|cache| cache.insert(filter, ids, generation))?;
+                    events
+                }
+                None => self.internal_query(filter).await?,
+            },
+        };
+
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("results", events.len());
+
+        Ok(events)
+    }
+
+    /// Get the events with the cached IDs
+    ///
+    /// Return `None` if any of them was removed or expired in the meantime,
+    /// since the older events that replace it in the results aren't cached.
+    async fn query_by_ids(
+        &self,
+        filter: Filter,
+        ids: Vec<EventId>,
+    ) -> Result<Option<Events>, Error> {
+        self.interact(move |db| {
+            let mut events: Events = Events::new(&filter);
+            let now: Timestamp = Timestamp::now();
+
+            let txn: RoTxn = db.read_txn()?;
+            for id in ids.iter() {
+                match db.get_event_by_id(&txn, id.as_bytes())? {
+                    Some(event) if !event.is_expired_at(&now) => {
+                        events.insert(event.into_owned());
+                    }
+                    _ => return Ok(None),
+                }
+            }
+            txn.commit()?;
+
+            Ok(Some(events))
+        })
+        .await?
+    }
+
+    // Lookup ID: EVENT_ORD_IMPL
+    async fn internal_query(&self, filter: Filter) -> Result<Events, Error> {
         self.interact(move |db| {
             let mut events: Events = Events::new(&filter);
 
@@ -276,6 +515,92 @@ impl Store {
         .await?
     }
 
+    pub async fn latest(&self, filter: Filter) -> Result<Option<(EventId, Timestamp)>, Error> {
+        self.interact(move |db| {
+            let txn = db.read_txn()?;
+            // Lookup ID: EVENT_ORD_IMPL
+            let latest = db
+                .query(&txn, filter.limit(1))?
+                .next()
+                .map(|e| (EventId::from_byte_array(*e.id), e.created_at));
+            txn.commit()?;
+            Ok(latest)
+        })
+        .await?
+    }
+
+    /// Walk the events on a blocking task, passing them to the callback through a bounded channel
+    pub async fn for_each<F>(&self, filter: Filter, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&Event) -> ControlFlow<()>,
+    {
+        let (tx, mut rx) = mpsc::channel::<Event>(FOR_EACH_CHANNEL_SIZE);
+
+        let db = self.db.clone();
+        let walk = task::spawn_blocking(move || -> Result<(), Error> {
+            // The read transaction is kept alive for the whole traversal
+            let txn = db.read_txn()?;
+            db.for_each(&txn, filter, |event| {
+                // The receiver is dropped when the callback stops the walk
+                match tx.blocking_send(event.clone().into_owned()) {
+                    Ok(()) => ControlFlow::Continue(()),
+                    Err(..) => ControlFlow::Break(()),
+                }
+            })?;
+            txn.commit()?;
+            Ok(())
+        });
+
+        while let Some(event) = rx.recv().await {
+            if f(&event).is_break() {
+                break;
+            }
+        }
+
+        // Stop the walk, if still running
+        drop(rx);
+
+        walk.await?
+    }
+
+    /// Blocking
+    pub fn for_each_blocking<F>(&self, filter: Filter, f: F) -> Result<(), Error>
+    where
+        F: FnMut(&EventBorrow) -> ControlFlow<()>,
+    {
+        // The read transaction is kept alive for the whole traversal
+        let txn = self.db.read_txn()?;
+        self.db.for_each(&txn, filter, f)?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    pub async fn events_since_seq(
+        &self,
+        seq: u64,
+        limit: usize,
+    ) -> Result<(Vec<Event>, u64), Error> {
+        self.interact(move |db| {
+            let txn = db.read_txn()?;
+            let (events, last) = db.events_since_seq(&txn, seq, limit)?;
+            let events: Vec<Event> = events.into_iter().map(|e| e.into_owned()).collect();
+            txn.commit()?;
+            Ok((events, last))
+        })
+        .await?
+    }
+
+    /// Get the IDs of the events matching the filter, without copying the events
+    pub async fn event_ids(&self, filter: Filter) -> Result<BTreeSet<EventId>, Error> {
+        self.interact(move |db| {
+            let txn = db.read_txn()?;
+            let ids: BTreeSet<EventId> = db.event_ids(&txn, filter)?;
+            txn.commit()?;
+            Ok(ids)
+        })
+        .await?
+    }
+
     pub async fn negentropy_items(
         &self,
         filter: Filter,
@@ -293,28 +618,201 @@ impl Store {
         .await?
     }
 
-    pub async fn delete(&self, filter: Filter) -> Result<(), Error> {
-        self.interact(move |db| {
-            let read_txn = db.read_txn()?;
-            let mut txn = db.write_txn()?;
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "delete",
+            level = "trace",
+            skip_all,
+            fields(
+                ids = filter.ids.as_ref().map_or(0, |ids| ids.len()),
+                authors = filter.authors.as_ref().map_or(0, |authors| authors.len()),
+                kinds = filter.kinds.as_ref().map_or(0, |kinds| kinds.len()),
+                limit = ?filter.limit,
+                results = tracing::field::Empty,
+            )
+        )
+    )]
+    pub async fn delete(&self, filter: Filter) -> Result<usize, Error> {
+        let deleted: usize = self
+            .interact(move |db| -> Result<usize, Error> {
+                let read_txn = db.read_txn()?;
+                let mut txn = db.write_txn()?;
+
+                let deleted: usize = db.delete(&read_txn, &mut txn, filter)?;
+
+                read_txn.commit()?;
+                txn.commit()?;
+
+                Ok(deleted)
+            })
+            .await??;
+
+        self.with_query_cache(|cache| cache.clear())?;
+
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("results", deleted);
+
+        Ok(deleted)
+    }
 
-            db.delete(&read_txn, &mut txn, filter)?;
+    pub async fn prune(&self) -> Result<PruneReport, Error> {
+        let report: PruneReport = self
+            .interact(move |db| -> Result<PruneReport, Error> {
+                // Acquire the write transaction before the read one (see `Store::save_event`)
+                let mut txn = db.write_txn()?;
+                let read_txn = db.read_txn()?;
 
-            read_txn.commit()?;
-            txn.commit()?;
+                let report: PruneReport = db.prune(&read_txn, &mut txn)?;
 
-            Ok(())
+                read_txn.commit()?;
+                txn.commit()?;
+
+                Ok(report)
+            })
+            .await??;
+
+        self.with_query_cache(|cache| cache.clear())?;
+
+        Ok(report)
+    }
+
+    pub async fn truncate_to(&self, keep_newest: usize) -> Result<usize, Error> {
+        let removed: usize = self
+            .interact(move |db| -> Result<usize, Error> {
+                let mut txn = db.write_txn()?;
+                let read_txn = db.read_txn()?;
+
+                let removed: usize = db.truncate_to(&read_txn, &mut txn, keep_newest)?;
+
+                read_txn.commit()?;
+                txn.commit()?;
+
+                Ok(removed)
+            })
+            .await??;
+
+        self.with_query_cache(|cache| cache.clear())?;
+
+        Ok(removed)
+    }
+
+    /// Spawn a task that periodically removes the expired events (NIP-40)
+    ///
+    /// Must be called within a runtime.
+    pub fn spawn_expiration_sweeper(&self, interval: Duration) -> AbortHandle {
+        let db: Lmdb = self.db.clone();
+        let query_cache: SharedQueryCache = self.query_cache.clone();
+        task::abortable(async move {
+            loop {
+                let db: Lmdb = db.clone();
+                let res: Result<usize, Error> = task::spawn_blocking(move || {
+                    db.remove_expired(&Timestamp::now(), EXPIRATION_SWEEP_BATCH_SIZE)
+                })
+                .await
+                .map_err(Error::from)
+                .and_then(|res| res);
+
+                // The removed events may be in the cached results
+                let res: Result<usize, Error> = res.and_then(|removed| {
+                    if removed > 0 {
+                        with_query_cache(&query_cache, |cache| cache.clear())?;
+                    }
+                    Ok(removed)
+                });
+
+                match res {
+                    Ok(..) => {}
+                    #[cfg(feature = "tracing")]
+                    Err(e) => tracing::error!(error = %e, "Can't remove expired events."),
+                    #[cfg(not(feature = "tracing"))]
+                    Err(..) => {}
+                }
+
+                time::sleep(interval).await;
+            }
         })
-        .await?
+    }
+
+    pub async fn flush(&self) -> Result<(), Error> {
+        self.interact(move |db| db.force_sync()).await?
     }
 
     pub async fn wipe(&self) -> Result<(), Error> {
-        self.interact(move |db| {
+        self.interact(move |db| -> Result<(), Error> {
             let mut txn = db.write_txn()?;
             db.wipe(&mut txn)?;
             txn.commit()?;
             Ok(())
         })
-        .await?
+        .await??;
+
+        self.with_query_cache(|cache| cache.clear())?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_prune() {
+        let temp = TempDir::new().unwrap();
+        let store = Store::open(temp.path()).unwrap();
+        let keys = Keys::generate();
+
+        let metadata = |name: &str, secs: u64| {
+            EventBuilder::metadata(&Metadata::new().name(name))
+                .custom_created_at(Timestamp::from_secs(secs))
+                .sign_with_keys(&keys)
+                .unwrap()
+        };
+
+        let live = metadata("live", 3000);
+        assert!(store.save_event(&live).await.unwrap().is_success());
+
+        // Nothing to prune
+        assert_eq!(store.prune().await.unwrap(), PruneReport::default());
+
+        // Store dead entries, bypassing the checks
+        let note = EventBuilder::text_note("Deleted")
+            .sign_with_keys(&keys)
+            .unwrap();
+        let events = vec![
+            metadata("first", 1000),
+            metadata("second", 2000),
+            note.clone(),
+        ];
+        store
+            .interact_with_fbb(move |db, fbb| -> Result<(), Error> {
+                let mut txn = db.write_txn()?;
+                let mut fbb = fbb.lock().map_err(|_| Error::MutexPoisoned)?;
+                for event in events.iter() {
+                    db.store(&mut txn, &mut fbb, event)?;
+                }
+                db.mark_deleted(&mut txn, &note.id)?;
+                txn.commit()?;
+                Ok(())
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(store.count(Filter::new()).await.unwrap(), 4);
+
+        assert_eq!(
+            store.prune().await.unwrap(),
+            PruneReport {
+                replaced: 2,
+                deleted: 1
+            }
+        );
+        assert_eq!(
+            store.query(Filter::new()).await.unwrap().to_vec(),
+            vec![live]
+        );
     }
 }
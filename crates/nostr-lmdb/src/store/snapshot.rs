@@ -0,0 +1,74 @@
+// Copyright (c) 2022-2023 Yuki Kishimoto
+// Copyright (c) 2023-2025 Rust Nostr Developers
+// Distributed under the MIT software license
+
+//! Point-in-time view of the store
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use async_utility::task;
+use heed::RoTxn;
+use nostr_database::prelude::*;
+
+use super::error::Error;
+use super::lmdb::Lmdb;
+
+/// Read-only view of the store, holding a read transaction until dropped
+#[derive(Clone)]
+pub struct Snapshot {
+    db: Lmdb,
+    txn: Arc<Mutex<RoTxn<'static>>>,
+}
+
+impl fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Snapshot").finish_non_exhaustive()
+    }
+}
+
+impl Snapshot {
+    pub(super) fn new(db: Lmdb) -> Result<Self, Error> {
+        let txn: RoTxn<'static> = db.static_read_txn()?;
+        Ok(Self {
+            db,
+            txn: Arc::new(Mutex::new(txn)),
+        })
+    }
+
+    async fn interact<F, R>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&Lmdb, &RoTxn) -> Result<R, Error> + Send + 'static,
+        R: Send + 'static,
+    {
+        let db = self.db.clone();
+        let txn = self.txn.clone();
+        task::spawn_blocking(move || {
+            let txn = txn.lock().map_err(|_| Error::MutexPoisoned)?;
+            f(&db, &txn)
+        })
+        .await?
+    }
+
+    pub async fn get_event_by_id(&self, id: &EventId) -> Result<Option<Event>, Error> {
+        let bytes = id.to_bytes();
+        self.interact(move |db, txn| Ok(db.get_event_by_id(txn, &bytes)?.map(|e| e.into_owned())))
+            .await
+    }
+
+    pub async fn count(&self, filter: Filter) -> Result<usize, Error> {
+        self.interact(move |db, txn| Ok(db.query(txn, filter)?.count()))
+            .await
+    }
+
+    // Lookup ID: EVENT_ORD_IMPL
+    pub async fn query(&self, filter: Filter) -> Result<Events, Error> {
+        self.interact(move |db, txn| {
+            let mut events: Events = Events::new(&filter);
+            let output = db.query(txn, filter)?;
+            events.extend(output.into_iter().map(|e| e.into_owned()));
+            Ok(events)
+        })
+        .await
+    }
+}
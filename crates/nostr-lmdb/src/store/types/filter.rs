@@ -16,6 +16,9 @@ pub struct DatabaseFilter {
     pub since: Option<Timestamp>,
     pub until: Option<Timestamp>,
     pub generic_tags: BTreeMap<SingleLetterTag, BTreeSet<String>>,
+    pub multi_letter_tags: BTreeMap<String, BTreeSet<String>>,
+    /// Exclude the events expired at this timestamp (NIP-40)
+    pub expired_at: Option<Timestamp>,
 }
 
 impl DatabaseFilter {
@@ -55,6 +58,16 @@ impl DatabaseFilter {
         })
     }
 
+    #[inline]
+    fn multi_letter_tag_match(&self, event: &EventBorrow) -> bool {
+        self.multi_letter_tags.iter().all(|(tag_name, set)| {
+            event.tags.iter().any(|tag| match tag.as_slice() {
+                [name, value, ..] => name == tag_name && set.contains(value.as_ref()),
+                _ => false,
+            })
+        })
+    }
+
     #[inline]
     fn kind_match(&self, event: &EventBorrow) -> bool {
         self.kinds.is_empty() || self.kinds.contains(&event.kind)
@@ -76,6 +89,14 @@ impl DatabaseFilter {
         }
     }
 
+    #[inline]
+    fn expiration_match(&self, event: &EventBorrow) -> bool {
+        match &self.expired_at {
+            Some(now) => !event.is_expired_at(now),
+            None => true,
+        }
+    }
+
     #[inline]
     pub fn match_event(&self, event: &EventBorrow) -> bool {
         self.ids_match(event)
@@ -84,12 +105,18 @@ impl DatabaseFilter {
             && self.since.map_or(true, |t| event.created_at >= t)
             && self.until.map_or(true, |t| event.created_at <= t)
             && self.tag_match(event)
+            && self.multi_letter_tag_match(event)
             && self.search_match(event)
+            && self.expiration_match(event)
     }
 }
 
 impl From<Filter> for DatabaseFilter {
     fn from(filter: Filter) -> Self {
+        // Drop the empty lists, so they aren't used for the index lookups.
+        // NOTE: the tags with no values, which match no event, must be checked before the conversion.
+        let filter: Filter = filter.normalized();
+
         Self {
             ids: filter
                 .ids
@@ -116,6 +143,8 @@ impl From<Filter> for DatabaseFilter {
             since: filter.since,
             until: filter.until,
             generic_tags: filter.generic_tags,
+            multi_letter_tags: filter.multi_letter_tags,
+            expired_at: Some(Timestamp::now()),
         }
     }
 }
@@ -159,7 +159,7 @@ impl NostrEventsDatabase for NdbDatabase {
         })
     }
 
-    fn delete(&self, _filter: Filter) -> BoxedFuture<Result<(), DatabaseError>> {
+    fn delete(&self, _filter: Filter) -> BoxedFuture<Result<usize, DatabaseError>> {
         Box::pin(async move { Err(DatabaseError::NotSupported) })
     }
 }
@@ -36,6 +36,8 @@ pub enum Error {
     ImpossibleToZap(String),
     /// Broken down filters for gossip are empty
     GossipFiltersEmpty,
+    /// The kind is not a relay list (NIP17 or NIP65)
+    InvalidRelayListKind(Kind),
     /// DMs relays not found
     DMsRelaysNotFound,
     /// Metadata not found
@@ -65,6 +67,9 @@ impl fmt::Display for Error {
             Self::GossipFiltersEmpty => {
                 write!(f, "gossip broken down filters are empty")
             }
+            Self::InvalidRelayListKind(kind) => {
+                write!(f, "kind {kind} is not a relay list")
+            }
             Self::DMsRelaysNotFound => write!(f, "DMs relays not found"),
             Self::MetadataNotFound => write!(f, "metadata not found"),
         }
@@ -4,12 +4,13 @@
 
 //! Client
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::future::Future;
 use std::iter;
 use std::sync::Arc;
 use std::time::Duration;
 
+use async_utility::futures_util::future;
 use nostr::prelude::*;
 use nostr_database::prelude::*;
 use nostr_relay_pool::__private::SharedState;
@@ -25,7 +26,7 @@ pub use self::error::Error;
 pub use self::options::Options;
 #[cfg(not(target_arch = "wasm32"))]
 pub use self::options::{Connection, ConnectionTarget};
-use crate::gossip::graph::{BrokenDownFilters, GossipGraph};
+use crate::gossip::graph::{BrokenDownFilters, GossipGraph, GossipMetrics, RelayEntry};
 
 /// Nostr client
 #[derive(Debug, Clone)]
@@ -93,7 +94,8 @@ impl Client {
         // Construct client
         Self {
             pool: RelayPool::__with_shared_state(builder.opts.pool, state),
-            gossip_graph: GossipGraph::new(),
+            gossip_graph: GossipGraph::new(builder.opts.gossip_max_relays_per_list)
+                .divide_limit(builder.opts.gossip_divide_limit),
             opts: builder.opts,
         }
     }
@@ -168,6 +170,44 @@ impl Client {
         self.pool.filtering()
     }
 
+    /// Get gossip graph metrics
+    ///
+    /// Useful to check the health of the gossip model (see [`Options::gossip`]).
+    #[inline]
+    pub async fn gossip_metrics(&self) -> Result<GossipMetrics, Error> {
+        self.gossip_graph.metrics().await
+    }
+
+    /// Get the raw NIP65 and NIP17 relay entries of a public key from the gossip graph
+    ///
+    /// Returns an empty list if no relay list is known for the public key.
+    #[inline]
+    pub async fn gossip_relay_entries(
+        &self,
+        public_key: PublicKey,
+    ) -> Result<Vec<RelayEntry>, Error> {
+        self.gossip_graph.relay_entries(public_key).await
+    }
+
+    /// Insert a relay list into the gossip graph, without a signed event
+    ///
+    /// Useful to seed the gossip graph with known relays.
+    /// The list replaces the existing one only if `created_at` is more recent.
+    ///
+    /// Only [`Kind::RelayList`] (NIP65) and [`Kind::InboxRelays`] (NIP17) are supported.
+    #[inline]
+    pub async fn gossip_insert_relay_list(
+        &self,
+        public_key: PublicKey,
+        kind: Kind,
+        relays: Vec<(RelayUrl, Option<RelayMetadata>)>,
+        created_at: Timestamp,
+    ) -> Result<(), Error> {
+        self.gossip_graph
+            .insert_relay_list(public_key, kind, relays, created_at)
+            .await
+    }
+
     /// Reset the client
     ///
     /// This method resets the client to simplify the switch to another account.
@@ -1247,25 +1287,74 @@ impl Client {
 
             // Get DISCOVERY and READ relays
             // TODO: avoid clone of both url and relay
-            let relays = self
+            let relays: HashMap<RelayUrl, Relay> = self
                 .pool
                 .relays_with_flag(
                     RelayServiceFlags::DISCOVERY | RelayServiceFlags::READ,
                     FlagCheck::Any,
                 )
-                .await
-                .into_keys();
+                .await;
 
-            // Get events from discovery and read relays
-            let events: Events = self
-                .fetch_events_from(relays, filter, Duration::from_secs(10))
-                .await?;
+            if relays.is_empty() {
+                return Err(Error::from(pool::Error::NoRelaysSpecified));
+            }
 
-            // Update last check for these public keys
+            // Update last check for these public keys before fetching,
+            // so that a failed fetch is still counted as an attempt
             self.gossip_graph
-                .update_last_check(outdated_public_keys)
+                .update_last_check(outdated_public_keys.iter().copied())
                 .await;
 
+            // Get events from discovery and read relays
+            let futures = relays.values().map(|relay| {
+                relay.fetch_events(
+                    filter.clone(),
+                    Duration::from_secs(10),
+                    ReqExitPolicy::default(),
+                )
+            });
+            let results = future::join_all(futures).await;
+
+            let mut events: Events = Events::new(&filter);
+            let mut completed: bool = false;
+
+            for (url, result) in relays.keys().zip(results.into_iter()) {
+                match result {
+                    Ok(list) => {
+                        events.extend(list);
+                        completed = true;
+                    }
+                    Err(e) => {
+                        tracing::error!(url = %url, error = %e, "Failed to fetch gossip lists.")
+                    }
+                }
+            }
+
+            if completed {
+                // At least one relay sent EOSE: update last success for these public keys,
+                // also if no list was found (i.e., never published)
+                self.gossip_graph
+                    .update_last_success(
+                        outdated_public_keys.iter().copied(),
+                        &[Kind::RelayList, Kind::InboxRelays],
+                    )
+                    .await;
+            } else {
+                // Update last success only for the lists actually received
+                let mut received: HashMap<PublicKey, Vec<Kind>> = HashMap::new();
+                for event in events.iter() {
+                    if outdated_public_keys.contains(&event.pubkey) {
+                        received.entry(event.pubkey).or_default().push(event.kind);
+                    }
+                }
+
+                for (public_key, kinds) in received.into_iter() {
+                    self.gossip_graph
+                        .update_last_success([public_key], &kinds)
+                        .await;
+                }
+            }
+
             // Merge database and relays events
             let merged: Events = events.merge(stored_events);
 
@@ -1289,23 +1378,54 @@ impl Client {
             .await?;
 
         // Broken-down filters
-        let filters: HashMap<RelayUrl, Filter> =
-            match self.gossip_graph.break_down_filter(filter).await {
-                BrokenDownFilters::Filters(filters) => filters,
-                BrokenDownFilters::Orphan(filter) | BrokenDownFilters::Other(filter) => {
-                    // Get read relays
-                    let read_relays = self
-                        .pool
-                        .relays_with_flag(RelayServiceFlags::READ, FlagCheck::All)
-                        .await;
-
-                    let mut map = HashMap::with_capacity(read_relays.len());
-                    for url in read_relays.into_keys() {
-                        map.insert(url, filter.clone());
+        let filters: HashMap<RelayUrl, Filter> = match self
+            .gossip_graph
+            .break_down_filter(filter.clone(), self.opts.gossip_allowed_relays)
+            .await
+        {
+            BrokenDownFilters::Filters(filters) => filters,
+            BrokenDownFilters::PartiallyOrphan {
+                mut filters,
+                orphan_authors,
+            } => {
+                // Get read relays
+                let read_relays = self
+                    .pool
+                    .relays_with_flag(RelayServiceFlags::READ, FlagCheck::All)
+                    .await;
+
+                // Send the orphan authors to the read relays
+                for url in read_relays.into_keys() {
+                    match filters.get_mut(&url) {
+                        Some(f) => {
+                            f.authors
+                                .get_or_insert_with(BTreeSet::new)
+                                .extend(orphan_authors.iter().copied());
+                        }
+                        None => {
+                            let mut orphan_filter: Filter = filter.clone();
+                            orphan_filter.authors = Some(orphan_authors.clone());
+                            filters.insert(url, orphan_filter);
+                        }
                     }
-                    map
                 }
-            };
+
+                filters
+            }
+            BrokenDownFilters::Orphan(filter) | BrokenDownFilters::Other(filter) => {
+                // Get read relays
+                let read_relays = self
+                    .pool
+                    .relays_with_flag(RelayServiceFlags::READ, FlagCheck::All)
+                    .await;
+
+                let mut map = HashMap::with_capacity(read_relays.len());
+                for url in read_relays.into_keys() {
+                    map.insert(url, filter.clone());
+                }
+                map
+            }
+        };
 
         // Add gossip (outbox and inbox) relays
         for url in filters.keys() {
@@ -1461,3 +1581,59 @@ impl Client {
         Ok(self.pool.sync_targeted(filters, opts).await?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nostr_relay_builder::prelude::*;
+
+    use super::*;
+    use crate::gossip::constant::MAX_FAILED_CHECKS;
+
+    #[tokio::test]
+    async fn test_update_outdated_gossip_graph_completed_fetch() {
+        // Mock relay
+        let mock = MockRelay::run().await.unwrap();
+        let url = mock.url();
+
+        let client = Client::builder().opts(Options::new().gossip(true)).build();
+        client.add_discovery_relay(&url).await.unwrap();
+        client
+            .try_connect_relay(&url, Duration::from_secs(3))
+            .await
+            .unwrap();
+
+        let public_key = Keys::generate().public_key;
+        let outdated = client.gossip_graph.check_outdated([public_key]).await;
+        assert!(outdated.contains(&public_key));
+
+        // The relay sent EOSE without lists: recorded as a success
+        client.update_outdated_gossip_graph(outdated).await.unwrap();
+
+        let outdated = client.gossip_graph.check_outdated([public_key]).await;
+        assert!(!outdated.contains(&public_key));
+    }
+
+    #[tokio::test]
+    async fn test_update_outdated_gossip_graph_failed_fetch() {
+        let client = Client::builder().opts(Options::new().gossip(true)).build();
+
+        // Never connected
+        client
+            .add_discovery_relay("ws://127.0.0.1:9")
+            .await
+            .unwrap();
+
+        let public_key = Keys::generate().public_key;
+
+        // Every failed fetch is counted as an attempt, up to the max
+        for _ in 0..MAX_FAILED_CHECKS {
+            let outdated = client.gossip_graph.check_outdated([public_key]).await;
+            assert!(outdated.contains(&public_key));
+
+            client.update_outdated_gossip_graph(outdated).await.unwrap();
+        }
+
+        let outdated = client.gossip_graph.check_outdated([public_key]).await;
+        assert!(!outdated.contains(&public_key));
+    }
+}
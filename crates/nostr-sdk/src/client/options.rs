@@ -12,6 +12,9 @@ use std::time::Duration;
 
 use nostr_relay_pool::prelude::*;
 
+use crate::gossip::constant::MAX_RELAYS_LIST;
+use crate::gossip::graph::GossipAllowedRelays;
+
 /// Options
 #[derive(Debug, Clone)]
 pub struct Options {
@@ -19,6 +22,9 @@ pub struct Options {
     pub(super) min_pow_difficulty: u8,
     pub(super) nip42_auto_authentication: bool,
     pub(super) gossip: bool,
+    pub(super) gossip_max_relays_per_list: usize,
+    pub(super) gossip_divide_limit: bool,
+    pub(super) gossip_allowed_relays: GossipAllowedRelays,
     #[cfg(not(target_arch = "wasm32"))]
     pub(super) connection: Connection,
     pub(super) relay_limits: RelayLimits,
@@ -34,6 +40,9 @@ impl Default for Options {
             min_pow_difficulty: 0,
             nip42_auto_authentication: true,
             gossip: false,
+            gossip_max_relays_per_list: MAX_RELAYS_LIST,
+            gossip_divide_limit: false,
+            gossip_allowed_relays: GossipAllowedRelays::default(),
             #[cfg(not(target_arch = "wasm32"))]
             connection: Connection::default(),
             relay_limits: RelayLimits::default(),
@@ -89,6 +98,36 @@ impl Options {
         self
     }
 
+    /// Max number of relays to use from every NIP17/NIP65 list of the gossip model (default: 5)
+    ///
+    /// Only the first relays of the lists are used. Values greater than 5 are capped.
+    #[inline]
+    pub fn gossip_max_relays_per_list(mut self, max: usize) -> Self {
+        self.gossip_max_relays_per_list = max;
+        self
+    }
+
+    /// Split the filter `limit` across the relays selected by the gossip model (default: false)
+    ///
+    /// When enabled, every relay is queried with a share of the `limit` proportional to the number of
+    /// public keys it's queried for, to avoid fetching up to `limit * relays` events.
+    /// When disabled, every relay is queried with the whole `limit`.
+    #[inline]
+    pub fn gossip_divide_limit(mut self, divide: bool) -> Self {
+        self.gossip_divide_limit = divide;
+        self
+    }
+
+    /// Relays allowed by the gossip model (default: all)
+    ///
+    /// The relays not allowed are excluded when breaking down the filters:
+    /// the public keys without any allowed relay are handled as if they had no relays.
+    #[inline]
+    pub fn gossip_allowed_relays(mut self, allowed: GossipAllowedRelays) -> Self {
+        self.gossip_allowed_relays = allowed;
+        self
+    }
+
     /// Connection mode and target
     #[inline]
     #[cfg(not(target_arch = "wasm32"))]
@@ -8,3 +8,5 @@ use std::time::Duration;
 pub const MAX_RELAYS_LIST: usize = 5;
 pub const PUBKEY_METADATA_OUTDATED_AFTER: Duration = Duration::from_secs(60 * 60); // 60 min
 pub const CHECK_OUTDATED_INTERVAL: Duration = Duration::from_secs(60 * 5); // 5 min
+/// Max number of checks retried before [`CHECK_OUTDATED_INTERVAL`] if the fetches keep failing
+pub const MAX_FAILED_CHECKS: u32 = 3;
@@ -8,7 +8,10 @@ use std::sync::Arc;
 use nostr::prelude::*;
 use tokio::sync::{RwLock, RwLockReadGuard};
 
-use super::constant::{CHECK_OUTDATED_INTERVAL, MAX_RELAYS_LIST, PUBKEY_METADATA_OUTDATED_AFTER};
+use super::constant::{
+    CHECK_OUTDATED_INTERVAL, MAX_FAILED_CHECKS, MAX_RELAYS_LIST, PUBKEY_METADATA_OUTDATED_AFTER,
+};
+use crate::client::Error;
 
 const P_TAG: SingleLetterTag = SingleLetterTag::lowercase(Alphabet::P);
 
@@ -16,12 +19,85 @@ const P_TAG: SingleLetterTag = SingleLetterTag::lowercase(Alphabet::P);
 pub enum BrokenDownFilters {
     /// Filters by url
     Filters(HashMap<RelayUrl, Filter>),
+    /// Filters by url for the authors with relays, plus the authors where no relays are available
+    PartiallyOrphan {
+        /// Filters by url
+        filters: HashMap<RelayUrl, Filter>,
+        /// Authors where no relays are available
+        orphan_authors: BTreeSet<PublicKey>,
+    },
     /// Filters that match a certain pattern but where no relays are available
     Orphan(Filter),
     /// Filters that can be sent to read relays (generic query, not related to public keys)
     Other(Filter),
 }
 
+/// Relays allowed by the gossip model
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GossipAllowedRelays {
+    /// Allow onion relays (default: true)
+    pub onion: bool,
+    /// Allow local network relays (default: true)
+    pub local: bool,
+    /// Allow relays without TLS (`ws://`) (default: true)
+    pub without_tls: bool,
+}
+
+impl Default for GossipAllowedRelays {
+    fn default() -> Self {
+        Self {
+            onion: true,
+            local: true,
+            without_tls: true,
+        }
+    }
+}
+
+impl GossipAllowedRelays {
+    /// Check if the relay is allowed
+    pub fn is_allowed(&self, url: &RelayUrl) -> bool {
+        if !self.onion && url.is_onion() {
+            return false;
+        }
+
+        if !self.local && url.is_local_addr() {
+            return false;
+        }
+
+        if !self.without_tls && url.scheme() == "ws" {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Gossip graph metrics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GossipMetrics {
+    /// Number of tracked public keys
+    pub public_keys: usize,
+    /// Number of public keys with a NIP65 relay list
+    pub nip65_lists: usize,
+    /// Number of public keys with a NIP17 relay list
+    pub nip17_lists: usize,
+    /// Number of distinct relay URLs
+    pub relays: usize,
+    /// Number of relay lists not updated for longer than [`PUBKEY_METADATA_OUTDATED_AFTER`]
+    pub outdated_lists: usize,
+}
+
+/// Relay entry of a NIP65 or NIP17 list
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RelayEntry {
+    /// Relay URL
+    pub url: RelayUrl,
+    /// Relay metadata (always `None` for NIP17 lists)
+    pub metadata: Option<RelayMetadata>,
+    /// Kind of the list the entry comes from ([`Kind::RelayList`] or [`Kind::InboxRelays`])
+    pub source_kind: Kind,
+}
+
 #[derive(Debug, Clone, Default)]
 struct RelayList<T> {
     pub collection: T,
@@ -29,34 +105,130 @@ struct RelayList<T> {
     pub event_created_at: Timestamp,
     /// Timestamp of when the metadata was updated
     pub last_update: Timestamp,
+    /// Timestamp of the last successful fetch
+    pub last_success: Timestamp,
 }
 
+/// Relay lists, in the same order of the events
 #[derive(Debug, Clone, Default)]
 struct RelayLists {
-    pub nip17: RelayList<HashSet<RelayUrl>>,
-    pub nip65: RelayList<HashMap<RelayUrl, Option<RelayMetadata>>>,
+    pub nip17: RelayList<Vec<RelayUrl>>,
+    pub nip65: RelayList<Vec<(RelayUrl, Option<RelayMetadata>)>>,
     /// Timestamp of the last check
     pub last_check: Timestamp,
+    /// Number of checks since the last successful fetch
+    pub failed_checks: u32,
 }
 
 type PublicKeyMap = HashMap<PublicKey, RelayLists>;
 
+/// Collect the first [`MAX_RELAYS_LIST`] relays, skipping duplicates and preserving the list order
+fn collect_relay_list<I, T, F>(iter: I, url: F) -> Vec<T>
+where
+    I: Iterator<Item = T>,
+    F: Fn(&T) -> &RelayUrl,
+{
+    let mut seen: HashSet<RelayUrl> = HashSet::new();
+    iter.filter(|item| seen.insert(url(item).clone()))
+        .take(MAX_RELAYS_LIST)
+        .collect()
+}
+
+/// Update the NIP65 list of the public key, only if `created_at` is more recent
+fn update_nip65_list<I>(
+    public_keys: &mut PublicKeyMap,
+    public_key: PublicKey,
+    relays: I,
+    created_at: Timestamp,
+) where
+    I: Iterator<Item = (RelayUrl, Option<RelayMetadata>)>,
+{
+    let lists: &mut RelayLists = public_keys.entry(public_key).or_default();
+
+    // Update only if new metadata has more recent timestamp
+    if created_at >= lists.nip65.event_created_at {
+        lists.nip65 = RelayList {
+            collection: collect_relay_list(relays, |(u, _)| u),
+            event_created_at: created_at,
+            last_update: Timestamp::now(),
+            last_success: lists.nip65.last_success,
+        };
+    }
+}
+
+/// Update the NIP17 list of the public key, only if `created_at` is more recent
+fn update_nip17_list<I>(
+    public_keys: &mut PublicKeyMap,
+    public_key: PublicKey,
+    relays: I,
+    created_at: Timestamp,
+) where
+    I: Iterator<Item = RelayUrl>,
+{
+    let lists: &mut RelayLists = public_keys.entry(public_key).or_default();
+
+    // Update only if new metadata has more recent timestamp
+    if created_at >= lists.nip17.event_created_at {
+        lists.nip17 = RelayList {
+            collection: collect_relay_list(relays, |u| u),
+            event_created_at: created_at,
+            last_update: Timestamp::now(),
+            last_success: lists.nip17.last_success,
+        };
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct GossipGraph {
     /// Keep track of seen public keys and of their NIP65
     public_keys: Arc<RwLock<PublicKeyMap>>,
+    /// Max number of relays to select from every list
+    max_relays_per_list: usize,
+    /// Split the filter limit across the relays
+    divide_limit: bool,
 }
 
 impl GossipGraph {
-    pub fn new() -> Self {
+    /// New gossip graph
+    ///
+    /// Only the first `max_relays_per_list` relays of every NIP17/NIP65 list will be selected
+    /// (capped to [`MAX_RELAYS_LIST`]).
+    pub fn new(max_relays_per_list: usize) -> Self {
         Self {
             public_keys: Arc::new(RwLock::new(HashMap::new())),
+            max_relays_per_list: max_relays_per_list.min(MAX_RELAYS_LIST),
+            divide_limit: false,
+        }
+    }
+
+    /// Split the filter `limit` across the relays in [`GossipGraph::break_down_filter`] (default: false)
+    ///
+    /// When enabled, every relay gets a share of the `limit` proportional to the number of public keys
+    /// it's queried for (at least 1). When disabled, every relay gets the whole `limit`.
+    pub fn divide_limit(mut self, divide: bool) -> Self {
+        self.divide_limit = divide;
+        self
+    }
+
+    /// Get the limit for a relay that covers `share` of the `total`
+    fn split_limit(&self, limit: Option<usize>, share: usize, total: usize) -> Option<usize> {
+        match limit {
+            Some(limit) if self.divide_limit && total > 0 => {
+                // Round up, to avoid under-fetching.
+                // Widen to `u128` to avoid overflows with huge limits (i.e., `usize::MAX`).
+                let limit: u128 = limit as u128;
+                let share: u128 = share as u128;
+                let total: u128 = total as u128;
+                let split: u128 = (limit * share + total - 1) / total;
+                Some(usize::try_from(split).unwrap_or(usize::MAX).max(1))
+            }
+            limit => limit,
         }
     }
 
     /// Update graph
     ///
-    /// Only the first [`MAX_RELAYS_LIST`] relays will be used.
+    /// Only the first [`MAX_RELAYS_LIST`] relays will be stored.
     pub async fn update<I>(&self, events: I)
     where
         I: IntoIterator<Item = Event>,
@@ -65,63 +237,57 @@ impl GossipGraph {
 
         for event in events.into_iter() {
             if event.kind == Kind::RelayList {
-                public_keys
-                    .entry(event.pubkey)
-                    .and_modify(|lists| {
-                        // Update only if new metadata has more recent timestamp
-                        if event.created_at >= lists.nip65.event_created_at {
-                            lists.nip65 = RelayList {
-                                collection: nip65::extract_relay_list(&event)
-                                    .take(MAX_RELAYS_LIST)
-                                    .map(|(u, m)| (u.clone(), *m))
-                                    .collect(),
-                                event_created_at: event.created_at,
-                                last_update: Timestamp::now(),
-                            };
-                        }
-                    })
-                    .or_insert_with(|| RelayLists {
-                        nip65: RelayList {
-                            collection: nip65::extract_relay_list(&event)
-                                .take(MAX_RELAYS_LIST)
-                                .map(|(u, m)| (u.clone(), *m))
-                                .collect(),
-                            event_created_at: event.created_at,
-                            last_update: Timestamp::now(),
-                        },
-                        ..Default::default()
-                    });
+                update_nip65_list(
+                    &mut public_keys,
+                    event.pubkey,
+                    nip65::extract_relay_list(&event).map(|(u, m)| (u.clone(), *m)),
+                    event.created_at,
+                );
             } else if event.kind == Kind::InboxRelays {
-                public_keys
-                    .entry(event.pubkey)
-                    .and_modify(|lists| {
-                        // Update only if new metadata has more recent timestamp
-                        if event.created_at >= lists.nip17.event_created_at {
-                            lists.nip17 = RelayList {
-                                collection: nip17::extract_relay_list(&event)
-                                    .take(MAX_RELAYS_LIST)
-                                    .cloned()
-                                    .collect(),
-                                event_created_at: event.created_at,
-                                last_update: Timestamp::now(),
-                            };
-                        }
-                    })
-                    .or_insert_with(|| RelayLists {
-                        nip17: RelayList {
-                            collection: nip17::extract_relay_list(&event)
-                                .take(MAX_RELAYS_LIST)
-                                .cloned()
-                                .collect(),
-                            event_created_at: event.created_at,
-                            last_update: Timestamp::now(),
-                        },
-                        ..Default::default()
-                    });
+                update_nip17_list(
+                    &mut public_keys,
+                    event.pubkey,
+                    nip17::extract_relay_list(&event).cloned(),
+                    event.created_at,
+                );
             }
         }
     }
 
+    /// Insert a relay list without a signed event
+    ///
+    /// Useful to seed the graph with known relays. The list replaces the existing one only if
+    /// `created_at` is more recent, as in [`GossipGraph::update`].
+    ///
+    /// Only [`Kind::RelayList`] and [`Kind::InboxRelays`] are supported.
+    /// The relay metadata is ignored for [`Kind::InboxRelays`].
+    pub async fn insert_relay_list(
+        &self,
+        public_key: PublicKey,
+        kind: Kind,
+        relays: Vec<(RelayUrl, Option<RelayMetadata>)>,
+        created_at: Timestamp,
+    ) -> Result<(), Error> {
+        match kind {
+            Kind::RelayList => {
+                let mut public_keys = self.public_keys.write().await;
+                update_nip65_list(&mut public_keys, public_key, relays.into_iter(), created_at);
+                Ok(())
+            }
+            Kind::InboxRelays => {
+                let mut public_keys = self.public_keys.write().await;
+                update_nip17_list(
+                    &mut public_keys,
+                    public_key,
+                    relays.into_iter().map(|(u, _)| u),
+                    created_at,
+                );
+                Ok(())
+            }
+            kind => Err(Error::InvalidRelayListKind(kind)),
+        }
+    }
+
     /// Check for what public keys the metadata are outdated or not existent (both for NIP17 and NIP65)
     pub async fn check_outdated<I>(&self, public_keys: I) -> HashSet<PublicKey>
     where
@@ -136,6 +302,16 @@ impl GossipGraph {
             match map.get(&public_key) {
                 Some(lists) => {
                     if lists.last_check + CHECK_OUTDATED_INTERVAL > now {
+                        // Recently checked: retry only if no list was successfully fetched
+                        // for too long, up to `MAX_FAILED_CHECKS` times
+                        let stale: bool = lists.nip17.last_success + PUBKEY_METADATA_OUTDATED_AFTER
+                            < now
+                            && lists.nip65.last_success + PUBKEY_METADATA_OUTDATED_AFTER < now;
+
+                        if stale && lists.failed_checks < MAX_FAILED_CHECKS {
+                            outdated.insert(public_key);
+                        }
+
                         continue;
                     }
 
@@ -162,6 +338,74 @@ impl GossipGraph {
         outdated
     }
 
+    /// Get graph metrics
+    pub async fn metrics(&self) -> Result<GossipMetrics, Error> {
+        let map = self.public_keys.read().await;
+        let now = Timestamp::now();
+
+        let mut metrics: GossipMetrics = GossipMetrics {
+            public_keys: map.len(),
+            ..Default::default()
+        };
+        let mut relays: HashSet<&RelayUrl> = HashSet::new();
+
+        for lists in map.values() {
+            if !lists.nip65.collection.is_empty() {
+                metrics.nip65_lists += 1;
+                relays.extend(lists.nip65.collection.iter().map(|(url, _)| url));
+
+                if lists.nip65.last_update + PUBKEY_METADATA_OUTDATED_AFTER < now {
+                    metrics.outdated_lists += 1;
+                }
+            }
+
+            if !lists.nip17.collection.is_empty() {
+                metrics.nip17_lists += 1;
+                relays.extend(lists.nip17.collection.iter());
+
+                if lists.nip17.last_update + PUBKEY_METADATA_OUTDATED_AFTER < now {
+                    metrics.outdated_lists += 1;
+                }
+            }
+        }
+
+        metrics.relays = relays.len();
+
+        Ok(metrics)
+    }
+
+    /// Get the raw relay entries of a public key, in the same order of the lists
+    ///
+    /// NIP65 entries come first, followed by the NIP17 ones.
+    pub async fn relay_entries(&self, public_key: PublicKey) -> Result<Vec<RelayEntry>, Error> {
+        let map = self.public_keys.read().await;
+
+        let lists: &RelayLists = match map.get(&public_key) {
+            Some(lists) => lists,
+            None => return Ok(Vec::new()),
+        };
+
+        let nip65 = lists
+            .nip65
+            .collection
+            .iter()
+            .map(|(url, metadata)| RelayEntry {
+                url: url.clone(),
+                metadata: *metadata,
+                source_kind: Kind::RelayList,
+            });
+        let nip17 = lists.nip17.collection.iter().map(|url| RelayEntry {
+            url: url.clone(),
+            metadata: None,
+            source_kind: Kind::InboxRelays,
+        });
+
+        Ok(nip65.chain(nip17).collect())
+    }
+
+    /// Record a fetch attempt
+    ///
+    /// Use [`GossipGraph::update_last_success`] to record that the fetch succeeded.
     pub async fn update_last_check<I>(&self, public_keys: I)
     where
         I: IntoIterator<Item = PublicKey>,
@@ -173,14 +417,41 @@ impl GossipGraph {
             map.entry(public_key)
                 .and_modify(|lists| {
                     lists.last_check = now;
+                    lists.failed_checks = lists.failed_checks.saturating_add(1);
                 })
                 .or_insert_with(|| RelayLists {
                     last_check: now,
+                    failed_checks: 1,
                     ..Default::default()
                 });
         }
     }
 
+    /// Record a successful fetch of the relay lists of the given kinds
+    ///
+    /// Only [`Kind::RelayList`] and [`Kind::InboxRelays`] are tracked, the other kinds are ignored.
+    pub async fn update_last_success<I>(&self, public_keys: I, kinds: &[Kind])
+    where
+        I: IntoIterator<Item = PublicKey>,
+    {
+        let mut map = self.public_keys.write().await;
+        let now = Timestamp::now();
+
+        for public_key in public_keys.into_iter() {
+            let lists: &mut RelayLists = map.entry(public_key).or_default();
+
+            for kind in kinds.iter() {
+                match kind {
+                    Kind::RelayList => lists.nip65.last_success = now,
+                    Kind::InboxRelays => lists.nip17.last_success = now,
+                    _ => {}
+                }
+            }
+
+            lists.failed_checks = 0;
+        }
+    }
+
     fn get_nip17_relays<'a, I>(
         &self,
         txn: &RwLockReadGuard<PublicKeyMap>,
@@ -193,7 +464,7 @@ impl GossipGraph {
 
         for public_key in public_keys.into_iter() {
             if let Some(lists) = txn.get(public_key) {
-                for url in lists.nip17.collection.iter() {
+                for url in lists.nip17.collection.iter().take(self.max_relays_per_list) {
                     urls.insert(url.clone());
                 }
             }
@@ -215,18 +486,21 @@ impl GossipGraph {
 
         for public_key in public_keys.into_iter() {
             if let Some(lists) = txn.get(public_key) {
-                for (url, m) in lists.nip65.collection.iter() {
-                    let insert: bool = match m {
+                let iter = lists
+                    .nip65
+                    .collection
+                    .iter()
+                    .filter(|(_, m)| match m {
                         Some(val) => match metadata {
                             Some(metadata) => val == &metadata,
                             None => true,
                         },
                         None => true,
-                    };
+                    })
+                    .take(self.max_relays_per_list);
 
-                    if insert {
-                        urls.insert(url.clone());
-                    }
+                for (url, _) in iter {
+                    urls.insert(url.clone());
                 }
             }
         }
@@ -246,7 +520,7 @@ impl GossipGraph {
 
         for public_key in public_keys.into_iter() {
             if let Some(lists) = txn.get(public_key) {
-                for url in lists.nip17.collection.iter() {
+                for url in lists.nip17.collection.iter().take(self.max_relays_per_list) {
                     urls.entry(url.clone())
                         .and_modify(|s| {
                             s.insert(*public_key);
@@ -273,20 +547,23 @@ impl GossipGraph {
 
         for public_key in public_keys.into_iter() {
             if let Some(lists) = txn.get(public_key) {
-                for (url, m) in lists.nip65.collection.iter() {
-                    let insert: bool = match m {
+                let iter = lists
+                    .nip65
+                    .collection
+                    .iter()
+                    .filter(|(_, m)| match m {
                         Some(val) => val == &metadata,
                         None => true,
-                    };
-
-                    if insert {
-                        urls.entry(url.clone())
-                            .and_modify(|s| {
-                                s.insert(*public_key);
-                            })
-                            .or_default()
-                            .insert(*public_key);
-                    }
+                    })
+                    .take(self.max_relays_per_list);
+
+                for (url, _) in iter {
+                    urls.entry(url.clone())
+                        .and_modify(|s| {
+                            s.insert(*public_key);
+                        })
+                        .or_default()
+                        .insert(*public_key);
                 }
             }
         }
@@ -350,7 +627,12 @@ impl GossipGraph {
         self.map_nip65_relays(txn, public_keys, RelayMetadata::Read)
     }
 
-    pub async fn break_down_filter(&self, filter: Filter) -> BrokenDownFilters {
+    /// Break down the filter by relay, excluding the relays not allowed
+    pub async fn break_down_filter(
+        &self,
+        filter: Filter,
+        allowed: GossipAllowedRelays,
+    ) -> BrokenDownFilters {
         let txn = self.public_keys.read().await;
 
         // Extract `p` tag from generic tags and parse public key hex
@@ -370,24 +652,43 @@ impl GossipGraph {
                 // Extend with NIP17 relays
                 outbox.extend(self.map_nip17_relays(&txn, authors));
 
+                // Exclude the relays not allowed
+                outbox.retain(|url, _| allowed.is_allowed(url));
+
                 // No relay available for the authors
                 if outbox.is_empty() {
                     return BrokenDownFilters::Orphan(filter);
                 }
 
+                // Collect the authors without relays
+                let orphan_authors: BTreeSet<PublicKey> = authors
+                    .iter()
+                    .filter(|pk| !outbox.values().any(|s| s.contains(pk)))
+                    .copied()
+                    .collect();
+
                 let mut map: HashMap<RelayUrl, Filter> = HashMap::with_capacity(outbox.len());
+                let total: usize = outbox.values().map(|s| s.len()).sum();
 
                 // Construct new filters
                 for (relay, pk_set) in outbox.into_iter() {
-                    // Clone filter and change authors
+                    // Clone filter and change authors and limit
                     let mut new_filter: Filter = filter.clone();
+                    new_filter.limit = self.split_limit(filter.limit, pk_set.len(), total);
                     new_filter.authors = Some(pk_set);
 
                     // Update map
                     map.insert(relay, new_filter);
                 }
 
-                BrokenDownFilters::Filters(map)
+                if orphan_authors.is_empty() {
+                    BrokenDownFilters::Filters(map)
+                } else {
+                    BrokenDownFilters::PartiallyOrphan {
+                        filters: map,
+                        orphan_authors,
+                    }
+                }
             }
             (None, Some(p_public_keys)) => {
                 // Get map of inbox relays
@@ -397,17 +698,22 @@ impl GossipGraph {
                 // Extend with NIP17 relays
                 inbox.extend(self.map_nip17_relays(&txn, p_public_keys));
 
+                // Exclude the relays not allowed
+                inbox.retain(|url, _| allowed.is_allowed(url));
+
                 // No relay available for the p tags
                 if inbox.is_empty() {
                     return BrokenDownFilters::Orphan(filter);
                 }
 
                 let mut map: HashMap<RelayUrl, Filter> = HashMap::with_capacity(inbox.len());
+                let total: usize = inbox.values().map(|s| s.len()).sum();
 
                 // Construct new filters
                 for (relay, pk_set) in inbox.into_iter() {
-                    // Clone filter and change p tags
+                    // Clone filter and change p tags and limit
                     let mut new_filter: Filter = filter.clone();
+                    new_filter.limit = self.split_limit(filter.limit, pk_set.len(), total);
                     new_filter
                         .generic_tags
                         .insert(P_TAG, pk_set.into_iter().map(|p| p.to_string()).collect());
@@ -426,16 +732,24 @@ impl GossipGraph {
                 // Extend with NIP17 relays
                 relays.extend(self.get_nip17_relays(&txn, authors.union(p_public_keys)));
 
+                // Exclude the relays not allowed
+                relays.retain(|url| allowed.is_allowed(url));
+
                 // No relay available for the authors and p tags
                 if relays.is_empty() {
                     return BrokenDownFilters::Orphan(filter);
                 }
 
                 let mut map: HashMap<RelayUrl, Filter> = HashMap::with_capacity(relays.len());
+                let total: usize = relays.len();
 
                 for relay in relays.into_iter() {
+                    // Clone filter and change limit
+                    let mut new_filter: Filter = filter.clone();
+                    new_filter.limit = self.split_limit(filter.limit, 1, total);
+
                     // Update map
-                    map.insert(relay, filter.clone());
+                    map.insert(relay, new_filter);
                 }
 
                 BrokenDownFilters::Filters(map)
@@ -481,11 +795,12 @@ mod tests {
     }
 
     async fn setup_graph() -> GossipGraph {
-        let graph = GossipGraph::new();
+        let graph = GossipGraph::new(MAX_RELAYS_LIST);
 
-        let mut events = Vec::new();
-        events.push(build_relay_list_event(SECRET_KEY_A, KEY_A_RELAYS.to_vec()));
-        events.push(build_relay_list_event(SECRET_KEY_B, KEY_B_RELAYS.to_vec()));
+        let events = vec![
+            build_relay_list_event(SECRET_KEY_A, KEY_A_RELAYS.to_vec()),
+            build_relay_list_event(SECRET_KEY_B, KEY_B_RELAYS.to_vec()),
+        ];
 
         graph.update(events).await;
 
@@ -509,7 +824,10 @@ mod tests {
 
         // Single author
         let filter = Filter::new().author(keys_a.public_key);
-        match graph.break_down_filter(filter.clone()).await {
+        match graph
+            .break_down_filter(filter.clone(), GossipAllowedRelays::default())
+            .await
+        {
             BrokenDownFilters::Filters(map) => {
                 assert_eq!(map.get(&damus_url).unwrap(), &filter);
                 assert_eq!(map.get(&nostr_bg_url).unwrap(), &filter);
@@ -521,7 +839,10 @@ mod tests {
 
         // Multiple authors
         let authors_filter = Filter::new().authors([keys_a.public_key, keys_b.public_key]);
-        match graph.break_down_filter(authors_filter.clone()).await {
+        match graph
+            .break_down_filter(authors_filter.clone(), GossipAllowedRelays::default())
+            .await
+        {
             BrokenDownFilters::Filters(map) => {
                 assert_eq!(map.get(&damus_url).unwrap(), &authors_filter);
                 assert_eq!(
@@ -548,7 +869,10 @@ mod tests {
 
         // Other filter
         let search_filter = Filter::new().search("Test").limit(10);
-        match graph.break_down_filter(search_filter.clone()).await {
+        match graph
+            .break_down_filter(search_filter.clone(), GossipAllowedRelays::default())
+            .await
+        {
             BrokenDownFilters::Other(filter) => {
                 assert_eq!(filter, search_filter);
             }
@@ -557,7 +881,10 @@ mod tests {
 
         // Single p tags
         let p_tag_filter = Filter::new().pubkey(keys_a.public_key);
-        match graph.break_down_filter(p_tag_filter.clone()).await {
+        match graph
+            .break_down_filter(p_tag_filter.clone(), GossipAllowedRelays::default())
+            .await
+        {
             BrokenDownFilters::Filters(map) => {
                 assert_eq!(map.get(&damus_url).unwrap(), &p_tag_filter);
                 assert_eq!(map.get(&nostr_bg_url).unwrap(), &p_tag_filter);
@@ -574,7 +901,10 @@ mod tests {
         let filter = Filter::new()
             .author(keys_a.public_key)
             .pubkey(keys_b.public_key);
-        match graph.break_down_filter(filter.clone()).await {
+        match graph
+            .break_down_filter(filter.clone(), GossipAllowedRelays::default())
+            .await
+        {
             BrokenDownFilters::Filters(map) => {
                 assert_eq!(map.get(&damus_url).unwrap(), &filter);
                 assert_eq!(map.get(&nostr_bg_url).unwrap(), &filter);
@@ -590,11 +920,347 @@ mod tests {
         // test orphan filters
         let random_keys = Keys::generate();
         let filter = Filter::new().author(random_keys.public_key);
-        match graph.break_down_filter(filter.clone()).await {
+        match graph
+            .break_down_filter(filter.clone(), GossipAllowedRelays::default())
+            .await
+        {
             BrokenDownFilters::Orphan(f) => {
                 assert_eq!(f, filter);
             }
             _ => panic!("Expected filters"),
         }
     }
+
+    #[tokio::test]
+    async fn test_break_down_filter_partially_orphan() {
+        let keys_a = Keys::parse(SECRET_KEY_A).unwrap();
+        let random_keys = Keys::generate();
+
+        let damus_url = RelayUrl::parse("wss://relay.damus.io").unwrap();
+
+        let graph = setup_graph().await;
+
+        let filter = Filter::new().authors([keys_a.public_key, random_keys.public_key]);
+        match graph
+            .break_down_filter(filter, GossipAllowedRelays::default())
+            .await
+        {
+            BrokenDownFilters::PartiallyOrphan {
+                filters,
+                orphan_authors,
+            } => {
+                assert_eq!(
+                    filters.get(&damus_url).unwrap(),
+                    &Filter::new().author(keys_a.public_key)
+                );
+                assert_eq!(orphan_authors, BTreeSet::from([random_keys.public_key]));
+            }
+            _ => panic!("Expected partially orphan filters"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_break_down_filter_allowed_relays() {
+        let keys_a = Keys::parse(SECRET_KEY_A).unwrap();
+        let onion_keys = Keys::generate();
+
+        let onion_url =
+            RelayUrl::parse("ws://oxtrdevav64z64yb7x6rjg4ntzqjhedm5b5zjqulugknhzr46ny2qbad.onion")
+                .unwrap();
+        let damus_url = RelayUrl::parse("wss://relay.damus.io").unwrap();
+
+        let graph = setup_graph().await;
+        let event = EventBuilder::relay_list([(onion_url.clone(), None)])
+            .sign_with_keys(&onion_keys)
+            .unwrap();
+        graph.update([event]).await;
+
+        let no_onion = GossipAllowedRelays {
+            onion: false,
+            ..Default::default()
+        };
+
+        // Allowed by default
+        let filter = Filter::new().author(onion_keys.public_key);
+        match graph
+            .break_down_filter(filter.clone(), GossipAllowedRelays::default())
+            .await
+        {
+            BrokenDownFilters::Filters(filters) => {
+                assert_eq!(filters.get(&onion_url).unwrap(), &filter);
+            }
+            _ => panic!("Expected filters"),
+        }
+
+        // Onion-only author becomes orphan
+        match graph.break_down_filter(filter.clone(), no_onion).await {
+            BrokenDownFilters::Orphan(f) => assert_eq!(f, filter),
+            _ => panic!("Expected orphan filter"),
+        }
+
+        // Mixed authors
+        let filter = Filter::new().authors([keys_a.public_key, onion_keys.public_key]);
+        match graph.break_down_filter(filter, no_onion).await {
+            BrokenDownFilters::PartiallyOrphan {
+                filters,
+                orphan_authors,
+            } => {
+                assert!(!filters.contains_key(&onion_url));
+                assert_eq!(
+                    filters.get(&damus_url).unwrap(),
+                    &Filter::new().author(keys_a.public_key)
+                );
+                assert_eq!(orphan_authors, BTreeSet::from([onion_keys.public_key]));
+            }
+            _ => panic!("Expected partially orphan filters"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_outdated_failed_checks() {
+        let graph = GossipGraph::new(MAX_RELAYS_LIST);
+        let public_key = Keys::generate().public_key;
+
+        // Attempts without successes are retried, up to the max
+        for _ in 0..MAX_FAILED_CHECKS {
+            let outdated = graph.check_outdated([public_key]).await;
+            assert!(outdated.contains(&public_key));
+            graph.update_last_check([public_key]).await;
+        }
+
+        let outdated = graph.check_outdated([public_key]).await;
+        assert!(!outdated.contains(&public_key));
+
+        // Recently checked with success
+        let public_key = Keys::generate().public_key;
+        graph.update_last_check([public_key]).await;
+        graph
+            .update_last_success([public_key], &[Kind::RelayList, Kind::InboxRelays])
+            .await;
+
+        let outdated = graph.check_outdated([public_key]).await;
+        assert!(!outdated.contains(&public_key));
+    }
+
+    #[tokio::test]
+    async fn test_check_outdated_fetched_without_lists() {
+        let graph = GossipGraph::new(MAX_RELAYS_LIST);
+        let public_key = Keys::generate().public_key;
+
+        let outdated = graph.check_outdated([public_key]).await;
+        assert!(outdated.contains(&public_key));
+
+        // The fetch completed but found no list: it's a success, not a failed check
+        graph.update_last_check([public_key]).await;
+        graph
+            .update_last_success([public_key], &[Kind::RelayList, Kind::InboxRelays])
+            .await;
+
+        for _ in 0..MAX_FAILED_CHECKS {
+            let outdated = graph.check_outdated([public_key]).await;
+            assert!(!outdated.contains(&public_key));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_relays_per_list() {
+        let keys = Keys::parse(SECRET_KEY_A).unwrap();
+
+        let urls: Vec<String> = (0..10)
+            .map(|i| format!("wss://relay{i}.example.com"))
+            .collect();
+        let relays: Vec<(&str, Option<RelayMetadata>)> =
+            urls.iter().map(|url| (url.as_str(), None)).collect();
+
+        let graph = GossipGraph::new(3);
+        graph
+            .update([build_relay_list_event(SECRET_KEY_A, relays)])
+            .await;
+
+        let filter = Filter::new().author(keys.public_key);
+        match graph
+            .break_down_filter(filter.clone(), GossipAllowedRelays::default())
+            .await
+        {
+            BrokenDownFilters::Filters(map) => {
+                let expected: HashSet<RelayUrl> = urls[..3]
+                    .iter()
+                    .map(|url| RelayUrl::parse(url).unwrap())
+                    .collect();
+                let selected: HashSet<RelayUrl> = map.keys().cloned().collect();
+                assert_eq!(selected, expected);
+            }
+            _ => panic!("Expected filters"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_divide_limit() {
+        let keys = Keys::parse(SECRET_KEY_A).unwrap();
+
+        let relays: Vec<(&str, Option<RelayMetadata>)> = vec![
+            ("wss://relay0.example.com", None),
+            ("wss://relay1.example.com", None),
+            ("wss://relay2.example.com", None),
+        ];
+        let event = build_relay_list_event(SECRET_KEY_A, relays);
+
+        let filter = Filter::new().author(keys.public_key).limit(9);
+
+        // Divide limit
+        let graph = GossipGraph::new(MAX_RELAYS_LIST).divide_limit(true);
+        graph.update([event.clone()]).await;
+
+        match graph
+            .break_down_filter(filter.clone(), GossipAllowedRelays::default())
+            .await
+        {
+            BrokenDownFilters::Filters(map) => {
+                assert_eq!(map.len(), 3);
+                for f in map.values() {
+                    assert_eq!(f.limit, Some(3));
+                }
+            }
+            _ => panic!("Expected filters"),
+        }
+
+        // Keep whole limit
+        let graph = GossipGraph::new(MAX_RELAYS_LIST);
+        graph.update([event]).await;
+
+        match graph
+            .break_down_filter(filter, GossipAllowedRelays::default())
+            .await
+        {
+            BrokenDownFilters::Filters(map) => {
+                assert_eq!(map.len(), 3);
+                for f in map.values() {
+                    assert_eq!(f.limit, Some(9));
+                }
+            }
+            _ => panic!("Expected filters"),
+        }
+    }
+
+    #[test]
+    fn test_split_limit_huge() {
+        let graph = GossipGraph::new(MAX_RELAYS_LIST).divide_limit(true);
+
+        assert_eq!(graph.split_limit(Some(usize::MAX), 1, 1), Some(usize::MAX));
+        assert_eq!(graph.split_limit(Some(usize::MAX), 3, 3), Some(usize::MAX));
+        assert_eq!(
+            graph.split_limit(Some(usize::MAX), 1, 2),
+            Some(usize::MAX / 2 + 1)
+        );
+        assert_eq!(graph.split_limit(Some(1), 1, usize::MAX), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let graph = setup_graph().await;
+
+        // Track a public key without lists
+        graph.update_last_check([Keys::generate().public_key]).await;
+
+        let metrics = graph.metrics().await.unwrap();
+        assert_eq!(
+            metrics,
+            GossipMetrics {
+                public_keys: 3,
+                nip65_lists: 2,
+                nip17_lists: 0,
+                // `wss://relay.damus.io` is in both lists
+                relays: 7,
+                outdated_lists: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_insert_relay_list() {
+        let graph = GossipGraph::new(MAX_RELAYS_LIST);
+
+        let keys = Keys::generate();
+        let write = RelayUrl::parse("wss://relay.damus.io").unwrap();
+        let read = RelayUrl::parse("wss://nos.lol").unwrap();
+        let now = Timestamp::now();
+
+        graph
+            .insert_relay_list(
+                keys.public_key,
+                Kind::RelayList,
+                vec![
+                    (write.clone(), Some(RelayMetadata::Write)),
+                    (read.clone(), Some(RelayMetadata::Read)),
+                ],
+                now,
+            )
+            .await
+            .unwrap();
+
+        let outbox = graph.get_nip65_outbox_relays(&[keys.public_key]).await;
+        assert_eq!(outbox, HashSet::from([write.clone()]));
+
+        // Older list is ignored
+        graph
+            .insert_relay_list(
+                keys.public_key,
+                Kind::RelayList,
+                vec![(read.clone(), Some(RelayMetadata::Write))],
+                now - 60,
+            )
+            .await
+            .unwrap();
+
+        let outbox = graph.get_nip65_outbox_relays(&[keys.public_key]).await;
+        assert_eq!(outbox, HashSet::from([write]));
+
+        // Not a relay list
+        assert!(matches!(
+            graph
+                .insert_relay_list(keys.public_key, Kind::TextNote, Vec::new(), now)
+                .await,
+            Err(Error::InvalidRelayListKind(Kind::TextNote))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_relay_entries() {
+        let graph = setup_graph().await;
+
+        let keys = Keys::parse(SECRET_KEY_A).unwrap();
+        let inbox = RelayUrl::parse("wss://inbox.nostr.wine").unwrap();
+        graph
+            .insert_relay_list(
+                keys.public_key,
+                Kind::InboxRelays,
+                vec![(inbox.clone(), None)],
+                Timestamp::now(),
+            )
+            .await
+            .unwrap();
+
+        let mut expected: Vec<RelayEntry> = KEY_A_RELAYS
+            .iter()
+            .map(|(url, metadata)| RelayEntry {
+                url: RelayUrl::parse(url).unwrap(),
+                metadata: *metadata,
+                source_kind: Kind::RelayList,
+            })
+            .collect();
+        expected.push(RelayEntry {
+            url: inbox,
+            metadata: None,
+            source_kind: Kind::InboxRelays,
+        });
+
+        assert_eq!(
+            graph.relay_entries(keys.public_key).await.unwrap(),
+            expected
+        );
+
+        // Unknown public key
+        let unknown = Keys::generate().public_key;
+        assert!(graph.relay_entries(unknown).await.unwrap().is_empty());
+    }
 }
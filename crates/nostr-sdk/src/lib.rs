@@ -39,3 +39,4 @@ mod gossip;
 pub mod prelude;
 
 pub use self::client::{Client, ClientBuilder, Options};
+pub use self::gossip::graph::{GossipAllowedRelays, GossipMetrics, RelayEntry};
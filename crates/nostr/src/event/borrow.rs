@@ -7,6 +7,7 @@
 use alloc::vec::Vec;
 use core::cmp::Ordering;
 use core::hash::{Hash, Hasher};
+use core::str::FromStr;
 
 use secp256k1::schnorr::Signature;
 
@@ -65,6 +66,28 @@ impl Hash for EventBorrow<'_> {
 }
 
 impl EventBorrow<'_> {
+    /// Get the expiration timestamp, if any
+    ///
+    /// <https://github.com/nostr-protocol/nips/blob/master/40.md>
+    pub fn expiration(&self) -> Option<Timestamp> {
+        self.tags.iter().find_map(|tag| match tag.as_slice() {
+            [kind, value, ..] if kind == "expiration" => Timestamp::from_str(value).ok(),
+            _ => None,
+        })
+    }
+
+    /// Returns `true` if the event has an expiration tag that is expired.
+    /// If an event has no expiration tag, then it will return `false`.
+    ///
+    /// <https://github.com/nostr-protocol/nips/blob/master/40.md>
+    #[inline]
+    pub fn is_expired_at(&self, now: &Timestamp) -> bool {
+        match self.expiration() {
+            Some(timestamp) => &timestamp < now,
+            None => false,
+        }
+    }
+
     /// Into owned event
     pub fn into_owned(self) -> Event {
         Event::new(
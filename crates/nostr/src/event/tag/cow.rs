@@ -43,6 +43,12 @@ impl<'a> CowTag<'a> {
         }
     }
 
+    /// Get as slice of strings
+    #[inline]
+    pub fn as_slice(&self) -> &[Cow<'a, str>] {
+        &self.buf
+    }
+
     /// Into owned tag
     pub fn into_owned(self) -> Tag {
         let buf: Vec<String> = self.buf.into_iter().map(|t| t.into_owned()).collect();
@@ -7,9 +7,11 @@
 
 use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 use core::fmt;
 use core::hash::Hash;
 use core::str::FromStr;
+use core::time::Duration;
 
 use serde::de::{Deserializer, MapAccess, Visitor};
 use serde::ser::{SerializeMap, Serializer};
@@ -20,6 +22,7 @@ use crate::nips::nip01::Coordinate;
 use crate::{Event, EventId, JsonUtil, Kind, PublicKey, Timestamp};
 
 type GenericTags = BTreeMap<SingleLetterTag, BTreeSet<String>>;
+type MultiLetterTags = BTreeMap<String, BTreeSet<String>>;
 
 const P_TAG: SingleLetterTag = SingleLetterTag::lowercase(Alphabet::P);
 
@@ -359,6 +362,16 @@ pub struct Filter {
     )]
     #[serde(default)]
     pub generic_tags: GenericTags,
+    /// Tag queries on multi-letter tag names (i.e., `#client`)
+    ///
+    /// Not part of NIP-01: relays index only the single-letter tags, so most of them ignore these.
+    #[serde(
+        flatten,
+        serialize_with = "serialize_multi_letter_tags",
+        deserialize_with = "deserialize_multi_letter_tags"
+    )]
+    #[serde(default)]
+    pub multi_letter_tags: MultiLetterTags,
 }
 
 impl Filter {
@@ -743,12 +756,123 @@ impl Filter {
         self
     }
 
+    /// Add multi-letter tag (i.e., `client`)
+    ///
+    /// Single-letter names must be added with [`Filter::custom_tag`]: this is a no-op for them.
+    pub fn multi_letter_tag<N, S>(self, name: N, value: S) -> Self
+    where
+        N: Into<String>,
+        S: Into<String>,
+    {
+        self.multi_letter_tags(name, [value])
+    }
+
+    /// Add multi-letter tags (i.e., `client`)
+    ///
+    /// Single-letter names must be added with [`Filter::custom_tags`]: this is a no-op for them.
+    pub fn multi_letter_tags<N, I, S>(mut self, name: N, values: I) -> Self
+    where
+        N: Into<String>,
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let name: String = name.into();
+
+        if name.chars().count() < 2 {
+            return self;
+        }
+
+        let values: BTreeSet<String> = values.into_iter().map(|v| v.into()).collect();
+        self.multi_letter_tags
+            .entry(name)
+            .or_default()
+            .extend(values);
+        self
+    }
+
     /// Check if [`Filter`] is empty
     #[inline]
     pub fn is_empty(&self) -> bool {
         self == &Filter::default()
     }
 
+    /// Check if any tag of the [`Filter`] has no values
+    ///
+    /// A tag with no values matches no event, so neither does the filter.
+    #[inline]
+    pub fn has_empty_tags(&self) -> bool {
+        self.generic_tags.values().any(|set| set.is_empty())
+            || self.multi_letter_tags.values().any(|set| set.is_empty())
+    }
+
+    /// Get the canonical form of the [`Filter`]
+    ///
+    /// Semantically identical filters have equal (and equally hashed) canonical forms,
+    /// so it can be used as key for caches or to dedupe subscriptions.
+    ///
+    /// The `ids`, `authors`, `kinds` and tag values are already sorted,
+    /// so only the empty lists (which match any event) are collapsed to `None`.
+    ///
+    /// The tags with no values are removed too, even if they match no event:
+    /// check [`Filter::has_empty_tags`] before normalizing a filter used to match events.
+    pub fn normalized(mut self) -> Self {
+        if matches!(&self.ids, Some(ids) if ids.is_empty()) {
+            self.ids = None;
+        }
+
+        if matches!(&self.authors, Some(authors) if authors.is_empty()) {
+            self.authors = None;
+        }
+
+        if matches!(&self.kinds, Some(kinds) if kinds.is_empty()) {
+            self.kinds = None;
+        }
+
+        self.generic_tags.retain(|_, set| !set.is_empty());
+        self.multi_letter_tags.retain(|_, set| !set.is_empty());
+
+        self
+    }
+
+    /// Split the `since`/`until` range into time windows
+    ///
+    /// Return a copy of the [`Filter`] for every window, from the oldest one.
+    /// Since the bounds are inclusive, every window covers `window` seconds starting at its `since`
+    /// and ends one second before the `since` of the next one. The last window ends at `until`.
+    ///
+    /// If `since` or `until` aren't set, `since` is greater than `until` or the `window` is
+    /// shorter than a second, the [`Filter`] is returned unchanged.
+    pub fn split_time_windows(self, window: Duration) -> Vec<Self> {
+        let step: u64 = window.as_secs();
+
+        let (since, until) = match (self.since, self.until) {
+            (Some(since), Some(until)) if since <= until && step > 0 => {
+                (since.as_u64(), until.as_u64())
+            }
+            _ => return vec![self],
+        };
+
+        let mut filters: Vec<Self> = Vec::new();
+        let mut start: u64 = since;
+
+        loop {
+            let end: u64 = start.saturating_add(step - 1).min(until);
+
+            let mut filter: Self = self.clone();
+            filter.since = Some(Timestamp::from_secs(start));
+            filter.until = Some(Timestamp::from_secs(end));
+            filters.push(filter);
+
+            if end >= until {
+                break;
+            }
+
+            start = end + 1;
+        }
+
+        filters
+    }
+
     /// Extract **all** public keys (both from `authors` and `#p`)
     pub fn extract_public_keys(&self) -> BTreeSet<PublicKey> {
         let mut public_keys: BTreeSet<PublicKey> = BTreeSet::new();
@@ -780,7 +904,7 @@ impl Filter {
 
     fn tag_match(&self, event: &Event) -> bool {
         if self.generic_tags.is_empty() {
-            return true;
+            return self.multi_letter_tag_match(event);
         }
 
         if event.tags.is_empty() {
@@ -797,6 +921,15 @@ impl Filter {
             } else {
                 false
             }
+        }) && self.multi_letter_tag_match(event)
+    }
+
+    fn multi_letter_tag_match(&self, event: &Event) -> bool {
+        self.multi_letter_tags.iter().all(|(tag_name, set)| {
+            event.tags.iter().any(|tag| match tag.as_slice() {
+                [name, value, ..] => name == tag_name && set.contains(value),
+                _ => false,
+            })
         })
     }
 
@@ -847,6 +980,56 @@ where
     map.end()
 }
 
+fn serialize_multi_letter_tags<S>(
+    multi_letter_tags: &MultiLetterTags,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut map = serializer.serialize_map(Some(multi_letter_tags.len()))?;
+    for (tag, values) in multi_letter_tags.iter() {
+        map.serialize_entry(&format!("#{tag}"), values)?;
+    }
+    map.end()
+}
+
+fn deserialize_multi_letter_tags<'de, D>(deserializer: D) -> Result<MultiLetterTags, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct MultiLetterTagsVisitor;
+
+    impl<'de> Visitor<'de> for MultiLetterTagsVisitor {
+        type Value = MultiLetterTags;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("map in which the keys are \"#XY...\" for some tag name")
+        }
+
+        fn visit_map<M>(self, mut map: M) -> Result<Self::Value, M::Error>
+        where
+            M: MapAccess<'de>,
+        {
+            let mut multi_letter_tags = BTreeMap::new();
+            while let Some(key) = map.next_key::<String>()? {
+                match key.strip_prefix('#') {
+                    Some(name) if name.chars().count() >= 2 => {
+                        let values: BTreeSet<String> = map.next_value()?;
+                        multi_letter_tags.insert(name.to_string(), values);
+                    }
+                    _ => {
+                        map.next_value::<serde::de::IgnoredAny>()?;
+                    }
+                }
+            }
+            Ok(multi_letter_tags)
+        }
+    }
+
+    deserializer.deserialize_map(MultiLetterTagsVisitor)
+}
+
 fn deserialize_generic_tags<'de, D>(deserializer: D) -> Result<GenericTags, D::Error>
 where
     D: Deserializer<'de>,
@@ -1082,6 +1265,140 @@ mod tests {
         assert!(filter.is_empty());
     }
 
+    #[test]
+    fn test_multi_letter_tags() {
+        let json = r##"{"kinds":[1],"#t":["nostr"],"#client":["app"]}"##;
+        let filter = Filter::from_json(json).unwrap();
+        assert_eq!(
+            filter,
+            Filter::new()
+                .kind(Kind::TextNote)
+                .hashtag("nostr")
+                .multi_letter_tag("client", "app")
+        );
+        assert_eq!(Filter::from_json(filter.as_json()).unwrap(), filter);
+
+        // Single-letter names are ignored
+        assert!(Filter::new().multi_letter_tag("t", "nostr").is_empty());
+
+        let event = Event::new(
+            EventId::all_zeros(),
+            PublicKey::from_str("379e863e8357163b5bce5d2688dc4f1dcc2d505222fb8d74db600f30535dfdfe")
+                .unwrap(),
+            Timestamp::from(0),
+            Kind::TextNote,
+            [
+                Tag::hashtag("nostr"),
+                Tag::parse(["client", "app"]).unwrap(),
+            ],
+            "",
+            Signature::from_str("273a9cd5d11455590f4359500bccb7a89428262b96b3ea87a756b770964472f8c3e87f5d5e64d8d2e859a71462a3f477b554565c4f2f326cb01dd7620db71502").unwrap(),
+        );
+        assert!(filter.match_event(&event));
+        assert!(Filter::new()
+            .multi_letter_tag("client", "app")
+            .match_event(&event));
+        assert!(!Filter::new()
+            .multi_letter_tag("client", "other")
+            .match_event(&event));
+        assert!(!Filter::new()
+            .multi_letter_tag("relay", "app")
+            .match_event(&event));
+    }
+
+    #[test]
+    fn test_split_time_windows() {
+        let since = Timestamp::from_secs(1_700_000_000);
+        let until = since + 86_399; // A day, inclusive
+        let filter = Filter::new().kind(Kind::TextNote).since(since).until(until);
+
+        let windows = filter
+            .clone()
+            .split_time_windows(Duration::from_secs(4 * 60 * 60));
+        assert_eq!(windows.len(), 6);
+
+        // Contiguous and non-overlapping coverage
+        assert_eq!(windows.first().unwrap().since, Some(since));
+        assert_eq!(windows.last().unwrap().until, Some(until));
+        for pair in windows.windows(2) {
+            assert_eq!(pair[0].until.unwrap() + 1, pair[1].since.unwrap());
+        }
+        for window in windows.iter() {
+            assert_eq!(
+                window.until.unwrap() - window.since.unwrap(),
+                Timestamp::from_secs(14_399)
+            );
+            assert_eq!(window.kinds, filter.kinds);
+        }
+
+        // Missing bounds
+        let filter = Filter::new().since(since);
+        assert_eq!(
+            filter.clone().split_time_windows(Duration::from_secs(60)),
+            vec![filter]
+        );
+    }
+
+    #[test]
+    fn test_filter_normalized() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::Hasher;
+
+        fn hash(filter: &Filter) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            filter.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        let pk1 =
+            PublicKey::from_hex("379e863e8357163b5bce5d2688dc4f1dcc2d505222fb8d74db600f30535dfdfe")
+                .unwrap();
+        let pk2 =
+            PublicKey::from_hex("b2d670de53b27691c0c3400225b65c35a26d06093bcc41f48ffc71e0907f9d4a")
+                .unwrap();
+
+        let a = Filter::new()
+            .authors([pk1, pk2])
+            .kind(Kind::TextNote)
+            .normalized();
+        let b = Filter {
+            ids: Some(BTreeSet::new()),
+            ..Filter::new().authors([pk2, pk1]).kind(Kind::TextNote)
+        }
+        .normalized();
+        assert_eq!(a, b);
+        assert_eq!(hash(&a), hash(&b));
+
+        // Empty lists match any event
+        let filter = Filter {
+            kinds: Some(BTreeSet::new()),
+            ..Filter::new()
+        };
+        assert!(filter.normalized().is_empty());
+
+        // Tags with no values are removed
+        let mut filter = Filter::new().custom_tags(
+            SingleLetterTag::lowercase(Alphabet::E),
+            Vec::<String>::new(),
+        );
+        filter
+            .multi_letter_tags
+            .insert(String::from("client"), BTreeSet::new());
+        assert!(!filter.is_empty());
+        assert!(filter.normalized().is_empty());
+
+        let a = Filter::new()
+            .kind(Kind::TextNote)
+            .custom_tags(
+                SingleLetterTag::lowercase(Alphabet::P),
+                Vec::<String>::new(),
+            )
+            .normalized();
+        let b = Filter::new().kind(Kind::TextNote).normalized();
+        assert_eq!(a, b);
+        assert_eq!(hash(&a), hash(&b));
+    }
+
     #[test]
     fn test_match_event() {
         let event_id =
@@ -1183,6 +1500,15 @@ mod tests {
         assert!(!filter.match_event(&event));
         assert!(!filter.match_event(&event_with_empty_tags));
 
+        // Not match (tags with no values)
+        let filter: Filter = Filter::new().custom_tags(
+            SingleLetterTag::lowercase(Alphabet::T),
+            Vec::<String>::new(),
+        );
+        assert!(filter.has_empty_tags());
+        assert!(!filter.match_event(&event));
+        assert!(!filter.match_event(&event_with_empty_tags));
+
         // Test match search
         let filter: Filter = Filter::new().search("test");
         assert!(filter.match_event(&event));
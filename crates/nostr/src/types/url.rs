@@ -146,6 +146,12 @@ impl RelayUrl {
             .is_some_and(|host| host.ends_with(".onion"))
     }
 
+    /// Get the scheme (`ws` or `wss`)
+    #[inline]
+    pub fn scheme(&self) -> &str {
+        self.url.scheme()
+    }
+
     /// Return the serialization of this relay URL without the trailing slash.
     ///
     /// This method will always remove the trailing slash.
@@ -371,6 +377,14 @@ mod tests {
         assert!(!url.is_local_addr());
     }
 
+    #[test]
+    fn test_scheme() {
+        let url = RelayUrl::parse("ws://127.0.0.1:7777").unwrap();
+        assert_eq!(url.scheme(), "ws");
+        let url = RelayUrl::parse("WSS://relay.damus.io").unwrap();
+        assert_eq!(url.scheme(), "wss");
+    }
+
     #[test]
     fn test_is_onion() {
         // Onion